@@ -1,35 +1,105 @@
-//! Load and store data in the form of JSON files in a folder hierarchy.
+//! Load and store data in the form of TOML files and generated objects.
 //!
 //! User-generated:
 //!
 //! - `ignore`: gitignore-like, used for stats, not during gathering
 //! - `authors.toml`: rename and consolidate authors
+//! - `mailmap`: git mailmap, applied before `authors.toml`
+//! - `annotations.toml`: events to mark in graphs
+//! - `config.toml`: defaults for command line options
+//! - `profiles/<name>/ignore`, `profiles/<name>/authors.toml`,
+//!   `profiles/<name>/mailmap`, `profiles/<name>/annotations.toml`: used
+//!   instead of the top-level files when the profile is selected. If a
+//!   profile lacks one of the files, the top-level file is used.
 //!
-//! Generated by blamegraph:
+//! Generated by blamegraph, either as files or in a single `data.redb`
+//! database, see [`Backend`]. Commits, blametrees and blames are compressed,
+//! see [`Compressed`]:
 //!
-//! - `log.json`: list of commits to use for stats, in reverse chronological order
-//! - `commits/<hash>.json`: metadata for a specific commit
-//! - `blames/<hash>.json`: blame data for a specific commit
+//! - `version.bin`: format version of the generated objects
+//! - `log.bin`: list of commits to use for stats, in reverse chronological order
+//! - `tags.bin`: tags pointing to commits in the log
+//! - `dictionary-<kind>.bin`: compression dictionary for a kind of object
+//! - `commits/<hash>.bin`: metadata for a specific commit
+//! - `blametrees/<hash>.bin`: blame ids of all files in a specific commit,
+//!   possibly as a delta to its first parent
+//! - `blames/<hash>.bin`: blame data for a specific file
+//! - `aggregates/<hash>-<files>-<settings>.bin`: line counts for a specific
+//!   commit, computed with specific `ignore`, `authors.toml` and
+//!   mailmap contents and specific options
 
+mod annotation;
 mod authors;
 mod blame;
+mod cache;
 mod commit;
+mod compression;
+mod config;
+mod id;
+mod mailmap;
+mod store;
+mod tag;
 
 use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
     fs,
+    hash::Hash,
     io::ErrorKind,
+    mem,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
 use anyhow::Context;
 use ignore::gitignore::{Gitignore, GitignoreBuilder};
-use lru::LruCache;
 use serde::{de::DeserializeOwned, Serialize};
-use tempfile::NamedTempFile;
+use sha2::Digest;
 
-pub use self::{authors::*, blame::*, commit::*};
+pub use self::{
+    annotation::*, authors::*, blame::*, cache::*, commit::*, compression::*, config::*, id::*,
+    mailmap::*, store::*, tag::*,
+};
+
+/// Version of the format generated objects are stored in. Bump it and add an
+/// upgrade step to the `migrate` command whenever a stored type changes.
+pub const FORMAT_VERSION: u32 = 4;
+
+/// Memory to use for caches if neither `--memory` nor the config file say
+/// otherwise.
+const DEFAULT_MEMORY: u64 = 1 << 30;
+
+/// Longest chain of deltas a blametree may be stored as, to bound the work
+/// needed to load it.
+const MAX_DELTA_DEPTH: u32 = 32;
+
+/// Error for an object that doesn't exist, as opposed to one that exists but
+/// can't be loaded.
+#[derive(Debug)]
+pub struct NotFound {
+    pub kind: Kind,
+    pub key: String,
+}
 
-const EXTENSION: &str = "bin";
+impl NotFound {
+    /// The [`NotFound`] among the error and its causes, if any.
+    pub fn find_in(e: &anyhow::Error) -> Option<&Self> {
+        e.chain().find_map(|cause| cause.downcast_ref::<Self>())
+    }
+}
+
+impl Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} not found", self.kind, self.key)
+    }
+}
+
+impl Error for NotFound {}
+
+fn path_profile(dir: &Path, profile: &str) -> PathBuf {
+    dir.join("profiles").join(profile)
+}
 
 fn path_ignore(dir: &Path) -> PathBuf {
     dir.join("ignore")
@@ -39,89 +109,339 @@ fn path_authors(dir: &Path) -> PathBuf {
     dir.join("authors.toml")
 }
 
-fn path_log(dir: &Path) -> PathBuf {
-    dir.join("log").with_extension(EXTENSION)
+fn path_mailmap(dir: &Path) -> PathBuf {
+    dir.join("mailmap")
 }
 
-fn path_commit(dir: &Path, hash: &str) -> PathBuf {
-    let first_two_chars = hash.split_at(2).0;
-    dir.join("commits")
-        .join(first_two_chars)
-        .join(hash)
-        .with_extension(EXTENSION)
+fn path_annotations(dir: &Path) -> PathBuf {
+    dir.join("annotations.toml")
 }
 
-fn path_blametree(dir: &Path, hash: &str) -> PathBuf {
-    let first_two_chars = hash.split_at(2).0;
-    dir.join("blametrees")
-        .join(first_two_chars)
-        .join(hash)
-        .with_extension(EXTENSION)
-}
-
-fn path_blame(dir: &Path, hash: &str) -> PathBuf {
-    let first_two_chars = hash.split_at(2).0;
-    dir.join("blames")
-        .join(first_two_chars)
-        .join(hash)
-        .with_extension(EXTENSION)
+fn path_config(dir: &Path) -> PathBuf {
+    dir.join("config.toml")
 }
 
 pub struct Data {
     pub dir: PathBuf,
-    commit_cache: LruCache<String, Commit>,
-    blametree_cache: LruCache<String, BlameTree>,
-    blame_cache: LruCache<String, Blame>,
+    pub profile: Option<String>,
+    /// Contents of `config.toml`, loaded once when opening the directory.
+    config: Config,
+    store: Compressed,
+    commit_cache: ShardedCache<Oid, Commit>,
+    blametree_cache: ShardedCache<Oid, BlameTree>,
+    blame_cache: ShardedCache<Sha256, Blame>,
+    /// Paths of loaded blametrees, shared between them to save memory.
+    paths: Interner,
+    /// Memory to use for all caches, including the ones commands create.
+    memory: u64,
+    /// Statistics of caches commands created, see [`Self::record_cache_stats`].
+    cache_stats: Mutex<Vec<(String, CacheStats)>>,
 }
 
 impl Data {
-    pub fn new(dir: PathBuf) -> Self {
-        Self {
+    /// Open a data directory. The caches use at most roughly `memory` bytes,
+    /// or what the config file says. Interned paths and the working memory of
+    /// commands, like their running totals, are not part of that budget. Only
+    /// if `create` is set, a new store may be created, see [`store::open`].
+    pub fn new(dir: PathBuf, memory: Option<u64>, create: bool) -> anyhow::Result<Self> {
+        let config = Self::load_config_from(&dir)?;
+        let memory = match (memory, &config.memory) {
+            (Some(memory), _) => memory,
+            (None, Some(memory)) => memory
+                .bytes()
+                .context("invalid memory entry in config file")?,
+            (None, None) => DEFAULT_MEMORY,
+        };
+
+        let store = Compressed::open(store::open(&dir, config.store, create)?)?;
+        Ok(Self {
             dir,
-            commit_cache: LruCache::new(10000.try_into().unwrap()),
-            blametree_cache: LruCache::new(100.try_into().unwrap()),
-            blame_cache: LruCache::new(10000.try_into().unwrap()),
+            profile: None,
+            config,
+            store,
+            // Half of the memory is left for the caches of commands.
+            commit_cache: ShardedCache::new(memory / 8),
+            blametree_cache: ShardedCache::new(memory / 8),
+            blame_cache: ShardedCache::new(memory / 4),
+            paths: Interner::default(),
+            memory,
+            cache_stats: Mutex::new(vec![]),
+        })
+    }
+
+    /// Memory a command may use for its own caches.
+    pub fn command_cache_memory(&self) -> u64 {
+        self.memory / 2
+    }
+
+    /// Remember the statistics of a cache a command created, to show them
+    /// with [`Self::cache_stats`].
+    pub fn record_cache_stats(&self, name: &str, stats: CacheStats) {
+        self.cache_stats
+            .lock()
+            .unwrap()
+            .push((name.to_string(), stats));
+    }
+
+    /// Statistics of the object caches and all recorded caches.
+    pub fn cache_stats(&self) -> Vec<(String, CacheStats)> {
+        let mut stats = vec![
+            ("commits".to_string(), self.commit_cache.stats()),
+            ("blametrees".to_string(), self.blametree_cache.stats()),
+            ("blames".to_string(), self.blame_cache.stats()),
+        ];
+        stats.extend(self.cache_stats.lock().unwrap().iter().cloned());
+        stats
+    }
+
+    pub fn select_profile(&mut self, profile: String) -> anyhow::Result<()> {
+        // Profiles are directories inside the data directory.
+        let is_path = matches!(profile.as_str(), "" | "." | "..") || profile.contains(['/', '\\']);
+        if is_path {
+            anyhow::bail!("invalid profile name {profile:?}");
+        }
+        let path = path_profile(&self.dir, &profile);
+        if !path.is_dir() {
+            anyhow::bail!("profile {profile} not found at {}", path.display());
         }
+        self.profile = Some(profile);
+        Ok(())
     }
 
-    fn load_data_uncached<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
-        Ok(bincode::deserialize(&fs::read(path)?)?)
+    /// Names of all profiles in the data directory.
+    pub fn profiles(&self) -> anyhow::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.dir.join("profiles")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => Err(e).context("failed to list profiles")?,
+        };
+
+        let mut profiles = vec![];
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                if let Some(name) = entry.file_name().to_str() {
+                    profiles.push(name.to_string());
+                }
+            }
+        }
+        profiles.sort_unstable();
+        Ok(profiles)
     }
 
-    fn load_data_cached<T: Clone + DeserializeOwned>(
-        cache: &mut LruCache<String, T>,
-        path: &Path,
-        key: String,
+    /// Pick the selected profile's version of a user-generated file if it
+    /// exists, the top-level version otherwise.
+    fn profile_path(&self, path: fn(&Path) -> PathBuf) -> PathBuf {
+        self.path_for_profile(self.profile.as_deref(), path)
+    }
+
+    /// Like [`Self::profile_path`], but for any profile.
+    fn path_for_profile(&self, profile: Option<&str>, path: fn(&Path) -> PathBuf) -> PathBuf {
+        if let Some(profile) = profile {
+            let profile_path = path(&path_profile(&self.dir, profile));
+            if profile_path.exists() {
+                return profile_path;
+            }
+        }
+        path(&self.dir)
+    }
+
+    /// Default location for output files, distinct per profile.
+    pub fn default_outfile(&self, name: &str, extension: &str) -> PathBuf {
+        // Profile names may contain dots, so the extension is appended
+        // instead of replacing whatever follows the last dot.
+        let name = match &self.profile {
+            Some(profile) => format!("{name}-{profile}.{extension}"),
+            None => format!("{name}.{extension}"),
+        };
+        self.dir.join(name)
+    }
+
+    /// The underlying store, with objects as they are stored.
+    pub fn store(&self) -> &dyn Store {
+        self.store.store()
+    }
+
+    /// Replace the store, returning the previous one.
+    pub fn replace_store(&mut self, store: Box<dyn Store>) -> anyhow::Result<Box<dyn Store>> {
+        self.commit_cache.clear();
+        self.blametree_cache.clear();
+        self.blame_cache.clear();
+        let store = Compressed::open(store)?;
+        Ok(std::mem::replace(&mut self.store, store).into_store())
+    }
+
+    /// Ensure all generated objects saved so far are persisted.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        self.store.flush()
+    }
+
+    /// Format version of the stored objects, or `None` if nothing is stored
+    /// yet. Data directories from before the version was recorded count as
+    /// version 1.
+    pub fn load_format_version(&self) -> anyhow::Result<Option<u32>> {
+        if let Some(bytes) = self.store.get(Kind::Meta, "version")? {
+            return Ok(Some(bincode::deserialize(&bytes)?));
+        }
+        if self.store.contains(Kind::Meta, "log")? {
+            return Ok(Some(1));
+        }
+        Ok(None)
+    }
+
+    pub fn save_format_version(&self, version: u32) -> anyhow::Result<()> {
+        self.save_data(Kind::Meta, "version", &version)
+            .context("failed to save format version")
+    }
+
+    /// Fail unless the stored objects can be read by this version of
+    /// blamegraph.
+    pub fn check_format_version(&self) -> anyhow::Result<()> {
+        match self.load_format_version()? {
+            None => Ok(()),
+            Some(FORMAT_VERSION) => Ok(()),
+            Some(version) if version > FORMAT_VERSION => anyhow::bail!(
+                "data directory has format version {version}, but this blamegraph only supports \
+                 up to version {FORMAT_VERSION}, please upgrade blamegraph"
+            ),
+            Some(version) => anyhow::bail!(
+                "data directory has format version {version}, but this blamegraph needs version \
+                 {FORMAT_VERSION}, run the migrate command to upgrade it"
+            ),
+        }
+    }
+
+    fn load_data<T: DeserializeOwned>(
+        store: &Compressed,
+        kind: Kind,
+        key: &str,
     ) -> anyhow::Result<T> {
+        let context = || format!("failed to load {kind} {key}");
+        let Some(bytes) = store.get(kind, key).with_context(context)? else {
+            let key = key.to_string();
+            return Err(NotFound { kind, key }.into());
+        };
+        let value = bincode::deserialize(&bytes).with_context(context)?;
+        Ok(value)
+    }
+
+    fn load_data_cached<K, T>(
+        store: &Compressed,
+        cache: &ShardedCache<K, T>,
+        kind: Kind,
+        key: K,
+    ) -> anyhow::Result<T>
+    where
+        K: Hash + Eq + Display + HeapSize,
+        T: Clone + DeserializeOwned + HeapSize,
+    {
         if let Some(value) = cache.get(&key) {
-            return Ok(value.clone());
+            return Ok(value);
         }
 
-        let value = Self::load_data_uncached::<T>(path)
-            .context(format!("failed to load {}", path.display()))?;
-        cache.push(key, value.clone());
+        let value = Self::load_data::<T>(store, kind, &key.to_string())?;
+        cache.put(key, value.clone());
         Ok(value)
     }
 
-    fn save_data<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
-        let parent = path.parent().unwrap();
-        fs::create_dir_all(parent)?;
-        let tmp_file = NamedTempFile::new_in(parent)?;
-        bincode::serialize_into(&tmp_file, value)?;
-        tmp_file.persist(path)?;
+    fn save_data<T: Serialize + ?Sized>(
+        &self,
+        kind: Kind,
+        key: &str,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        self.save_serialized(kind, key, &bincode::serialize(value)?)
+    }
+
+    /// Load an object without deserializing it.
+    pub fn load_serialized(&self, kind: Kind, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.store.get(kind, key)
+    }
+
+    /// Save an already serialized object, overwriting any existing one.
+    pub fn save_serialized(&self, kind: Kind, key: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        self.store.put(kind, key, bytes)
+    }
+
+    /// Train a compression dictionary for a kind of object from serialized
+    /// samples. Returns whether a dictionary was trained.
+    pub fn train_dictionary(&mut self, kind: Kind, samples: &[Vec<u8>]) -> anyhow::Result<bool> {
+        self.store.train_dictionary(kind, samples)
+    }
+
+    /// Compress objects without the current dictionaries from now on, so they
+    /// can be replaced once all objects have been rewritten.
+    pub fn retire_dictionaries(&mut self) {
+        self.store.retire_dictionaries();
+    }
+
+    pub fn remove_retired_dictionaries(&mut self) -> anyhow::Result<()> {
+        self.store.remove_retired_dictionaries()
+    }
+
+    /// Train compression dictionaries from the stored objects for the kinds
+    /// that have none yet, and recompress the objects with them.
+    pub fn train_dictionaries(&mut self) -> anyhow::Result<()> {
+        for kind in [Kind::Commit, Kind::BlameTree, Kind::Blame] {
+            if self.store.has_dictionary(kind) {
+                continue;
+            }
+
+            let keys = self.store().keys(kind)?;
+            let step = (keys.len() / MAX_SAMPLES).max(1);
+            let mut samples = vec![];
+            for key in keys.iter().step_by(step) {
+                samples.extend(self.store.get(kind, key)?);
+            }
+            if !self.train_dictionary(kind, &samples)? {
+                continue;
+            }
+
+            for key in keys {
+                let Some(bytes) = self.store().get(kind, &key)? else {
+                    continue;
+                };
+                if Compressed::lacks_dictionary(&bytes) {
+                    let bytes = self.store.decompress(kind, bytes)?;
+                    self.save_serialized(kind, &key, &bytes)?;
+                }
+            }
+        }
         Ok(())
     }
 
-    fn save_data_without_overwriting<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
-        if path.exists() {
+    fn save_data_without_overwriting<T: Serialize>(
+        &self,
+        kind: Kind,
+        key: &str,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        if self.store.contains(kind, key)? {
             return Ok(());
         }
-        Self::save_data(path, value)
+        self.save_data(kind, key, value)
     }
 
     pub fn load_ignore_uncached(&self) -> anyhow::Result<Gitignore> {
+        Self::load_ignore_from(&self.profile_path(path_ignore))
+    }
+
+    /// The ignore rules of the top level and of every profile, falling back to
+    /// the top-level file like [`Self::load_ignore_uncached`].
+    pub fn load_all_ignores_uncached(&self) -> anyhow::Result<Vec<Gitignore>> {
+        let mut ignores = vec![Self::load_ignore_from(&path_ignore(&self.dir))?];
+        for profile in self.profiles()? {
+            let path = path_ignore(&path_profile(&self.dir, &profile));
+            if path.exists() {
+                ignores.push(Self::load_ignore_from(&path)?);
+            }
+        }
+        Ok(ignores)
+    }
+
+    fn load_ignore_from(path: &Path) -> anyhow::Result<Gitignore> {
         let mut builder = GitignoreBuilder::new(PathBuf::new());
-        match builder.add(path_ignore(&self.dir)) {
+        match builder.add(path) {
             // Very likely just a "file not found" error
             Some(ignore::Error::WithPath { .. }) => Ok(()),
             Some(e) => Err(e),
@@ -132,71 +452,357 @@ impl Data {
     }
 
     pub fn load_authors_uncached(&self) -> anyhow::Result<Authors> {
-        let path = path_authors(&self.dir);
-        let authors = match fs::read_to_string(&path) {
-            Ok(s) => toml::from_str::<Authors>(&s)?,
+        let path = self.profile_path(path_authors);
+        let mut authors = match fs::read_to_string(&path) {
+            Ok(s) => Authors::parse(&s)?,
             Err(e) if e.kind() == ErrorKind::NotFound => Authors::default(),
             Err(e) => Err(e).context(format!("failed to load authors from {}", path.display()))?,
         };
+        authors.compile()?;
         authors.check_for_cycles()?;
         Ok(authors)
     }
 
-    pub fn load_log_uncached(&self) -> anyhow::Result<Vec<String>> {
-        let path = path_log(&self.dir);
-        let log = match fs::read(&path) {
-            Ok(s) => bincode::deserialize::<Vec<String>>(&s)?,
-            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
-            Err(e) => Err(e).context(format!("failed to load log from {}", path.display()))?,
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn load_config_from(dir: &Path) -> anyhow::Result<Config> {
+        let path = path_config(dir);
+        let config = match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str::<Config>(&s)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Config::default(),
+            Err(e) => Err(e).context(format!("failed to load config from {}", path.display()))?,
+        };
+        Ok(config)
+    }
+
+    pub fn load_annotations_uncached(&self) -> anyhow::Result<Annotations> {
+        let path = self.profile_path(path_annotations);
+        let annotations = match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str::<Annotations>(&s)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Annotations::default(),
+            Err(e) => Err(e).context(format!(
+                "failed to load annotations from {}",
+                path.display()
+            ))?,
+        };
+        annotations.check()?;
+        Ok(annotations)
+    }
+
+    /// Hash of the contents of the `ignore`, `authors.toml` and mailmap files
+    /// in effect, to detect when stats computed with them are outdated. An
+    /// explicit mailmap path takes precedence like in
+    /// [`Self::load_mailmap_uncached`].
+    pub fn hash_user_files(&self, mailmap: Option<&Path>) -> anyhow::Result<Sha256> {
+        self.hash_user_files_for_profile(self.profile.as_deref(), mailmap)
+    }
+
+    /// Hashes like [`Self::hash_user_files`] for the top level and every
+    /// profile, without an explicit mailmap.
+    pub fn hash_user_files_of_all_profiles(&self) -> anyhow::Result<Vec<Sha256>> {
+        let mut hashes = vec![self.hash_user_files_for_profile(None, None)?];
+        for profile in self.profiles()? {
+            hashes.push(self.hash_user_files_for_profile(Some(&profile), None)?);
+        }
+        Ok(hashes)
+    }
+
+    fn hash_user_files_for_profile(
+        &self,
+        profile: Option<&str>,
+        mailmap: Option<&Path>,
+    ) -> anyhow::Result<Sha256> {
+        let mailmap = match mailmap {
+            Some(path) => path.to_path_buf(),
+            None => self.path_for_profile(profile, path_mailmap),
+        };
+
+        let mut hasher = sha2::Sha256::new();
+        for path in [
+            self.path_for_profile(profile, path_ignore),
+            self.path_for_profile(profile, path_authors),
+            mailmap,
+        ] {
+            // The length prefix keeps the boundaries between files unambiguous.
+            match fs::read(&path) {
+                Ok(bytes) => {
+                    hasher.update((bytes.len() as u64).to_le_bytes());
+                    hasher.update(bytes);
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => hasher.update(u64::MAX.to_le_bytes()),
+                Err(e) => Err(e).context(format!("failed to read {}", path.display()))?,
+            }
+        }
+        Ok(Sha256(hasher.finalize().into()))
+    }
+
+    /// Add entries to the authors file, leaving existing entries and comments
+    /// untouched. The entries are inserted before the first table so they stay
+    /// top-level keys.
+    pub fn add_authors(&self, toml: &str) -> anyhow::Result<()> {
+        let path = self.profile_path(path_authors);
+        let text = match fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => Err(e).context(format!("failed to load authors from {}", path.display()))?,
+        };
+
+        let mut lines = text.lines().collect::<Vec<_>>();
+        let mut first_table = lines
+            .iter()
+            .position(|l| l.trim_start().starts_with('['))
+            .unwrap_or(lines.len());
+        if first_table < lines.len() {
+            lines.insert(first_table, "");
+        }
+        if first_table > 0 && !lines[first_table - 1].trim().is_empty() {
+            lines.insert(first_table, "");
+            first_table += 1;
+        }
+        lines.insert(first_table, toml.trim());
+
+        let mut text = lines.join("\n");
+        text.push('\n');
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, text).context(format!("failed to save authors to {}", path.display()))
+    }
+
+    /// Load the mailmap from the data directory, or from an explicit path.
+    pub fn load_mailmap_uncached(&self, path: Option<&Path>) -> anyhow::Result<Mailmap> {
+        if let Some(path) = path {
+            let mailmap = fs::read_to_string(path)
+                .context(format!("failed to load mailmap from {}", path.display()))?;
+            return Ok(Mailmap::parse(&mailmap));
+        }
+
+        let path = self.profile_path(path_mailmap);
+        let mailmap = match fs::read_to_string(&path) {
+            Ok(s) => Mailmap::parse(&s),
+            Err(e) if e.kind() == ErrorKind::NotFound => Mailmap::default(),
+            Err(e) => Err(e).context(format!("failed to load mailmap from {}", path.display()))?,
+        };
+        Ok(mailmap)
+    }
+
+    pub fn save_mailmap(&self, mailmap: &str) -> anyhow::Result<()> {
+        let path = path_mailmap(&self.dir);
+        fs::create_dir_all(&self.dir)?;
+        fs::write(&path, mailmap).context(format!("failed to save mailmap to {}", path.display()))
+    }
+
+    pub fn load_log_uncached(&self) -> anyhow::Result<Vec<Oid>> {
+        let log = match self.store.get(Kind::Meta, "log")? {
+            Some(bytes) => bincode::deserialize::<Vec<Oid>>(&bytes)?,
+            None => vec![],
         };
         Ok(log)
     }
 
-    pub fn save_log(&self, log: &Vec<String>) -> anyhow::Result<()> {
-        let path = path_log(&self.dir);
-        Self::save_data(&path, log).context(format!("failed to save log to {}", path.display()))
+    pub fn save_log(&self, log: &[Oid]) -> anyhow::Result<()> {
+        self.save_data(Kind::Meta, "log", log)
+            .context("failed to save log")
     }
 
-    pub fn load_commit_cached(&mut self, hash: String) -> anyhow::Result<Commit> {
-        let path = path_commit(&self.dir, &hash);
-        Self::load_data_cached(&mut self.commit_cache, &path, hash)
+    pub fn load_tags_uncached(&self) -> anyhow::Result<Vec<Tag>> {
+        let tags = match self.store.get(Kind::Meta, "tags")? {
+            Some(bytes) => bincode::deserialize::<Vec<Tag>>(&bytes)?,
+            None => vec![],
+        };
+        Ok(tags)
+    }
+
+    pub fn save_tags(&self, tags: &[Tag]) -> anyhow::Result<()> {
+        self.save_data(Kind::Meta, "tags", tags)
+            .context("failed to save tags")
+    }
+
+    pub fn load_commit_cached(&self, hash: Oid) -> anyhow::Result<Commit> {
+        Self::load_data_cached(&self.store, &self.commit_cache, Kind::Commit, hash)
+    }
+
+    pub fn load_commit_uncached(&self, hash: Oid) -> anyhow::Result<Commit> {
+        Self::load_data(&self.store, Kind::Commit, &hash.to_string())
     }
 
     pub fn save_commit(&self, commit: &Commit) -> anyhow::Result<()> {
-        let path = path_commit(&self.dir, &commit.hash);
-        Self::save_data_without_overwriting(&path, commit)
+        self.save_data_without_overwriting(Kind::Commit, &commit.hash.to_string(), commit)
     }
 
-    pub fn blametree_exists(&self, hash: String) -> bool {
-        path_blametree(&self.dir, &hash).exists()
+    pub fn blametree_exists(&self, hash: Oid) -> anyhow::Result<bool> {
+        self.store.contains(Kind::BlameTree, &hash.to_string())
     }
 
-    pub fn load_blametree_cached(&mut self, hash: String) -> anyhow::Result<BlameTree> {
-        let path = path_blametree(&self.dir, &hash);
-        Self::load_data_cached(&mut self.blametree_cache, &path, hash)
+    /// Load a blametree, applying deltas until a full or cached blametree is
+    /// reached. All blametrees along the way are cached if a cache is given.
+    fn load_blametree(
+        store: &Compressed,
+        paths: &Interner,
+        cache: Option<&ShardedCache<Oid, BlameTree>>,
+        hash: Oid,
+    ) -> anyhow::Result<BlameTree> {
+        let mut deltas = vec![];
+        let mut next = hash;
+        let mut blametree = loop {
+            if let Some(blametree) = cache.and_then(|c| c.get(&next)) {
+                break blametree;
+            }
+            match Self::load_data::<StoredBlameTree>(store, Kind::BlameTree, &next.to_string())? {
+                StoredBlameTree::Full(mut blametree) => {
+                    Self::intern_paths(paths, &mut blametree.blames, Some(&mut blametree.classes));
+                    if let Some(cache) = cache {
+                        cache.put(next, blametree.clone());
+                    }
+                    break blametree;
+                }
+                StoredBlameTree::Delta(mut delta) => {
+                    Self::intern_paths(paths, &mut delta.changed, delta.classes.as_mut());
+                    for path in &mut delta.removed {
+                        paths.intern(path);
+                    }
+                    next = delta.parent;
+                    deltas.push(delta);
+                }
+            }
+        };
+
+        while let Some(delta) = deltas.pop() {
+            blametree = delta.apply(blametree);
+            if let Some(cache) = cache {
+                cache.put(blametree.commit, blametree.clone());
+            }
+        }
+        Ok(blametree)
+    }
+
+    /// Make the paths of freshly loaded blame ids and classes share
+    /// allocations with all other loaded paths.
+    fn intern_paths(
+        paths: &Interner,
+        blames: &mut [BlameId],
+        classes: Option<&mut HashMap<Arc<str>, FileClass>>,
+    ) {
+        for blame in blames {
+            paths.intern(&mut blame.path);
+        }
+        if let Some(classes) = classes {
+            *classes = mem::take(classes)
+                .into_iter()
+                .map(|(mut path, class)| {
+                    paths.intern(&mut path);
+                    (path, class)
+                })
+                .collect();
+        }
+    }
+
+    pub fn load_blametree_cached(&self, hash: Oid) -> anyhow::Result<BlameTree> {
+        let cache = Some(&self.blametree_cache);
+        Self::load_blametree(&self.store, &self.paths, cache, hash)
+    }
+
+    pub fn load_blametree_uncached(&self, hash: Oid) -> anyhow::Result<BlameTree> {
+        Self::load_blametree(&self.store, &self.paths, None, hash)
+    }
+
+    /// The blametree a stored blametree is a delta to, if any.
+    pub fn load_blametree_parent(&self, hash: Oid) -> anyhow::Result<Option<Oid>> {
+        match Self::load_data(&self.store, Kind::BlameTree, &hash.to_string())? {
+            StoredBlameTree::Full(_) => Ok(None),
+            StoredBlameTree::Delta(delta) => Ok(Some(delta.parent)),
+        }
+    }
+
+    /// The delta a blametree is stored as, or `None` if it is stored in full.
+    pub fn load_blametree_delta(&self, hash: Oid) -> anyhow::Result<Option<BlameTreeDelta>> {
+        match Self::load_data(&self.store, Kind::BlameTree, &hash.to_string())? {
+            StoredBlameTree::Full(_) => Ok(None),
+            StoredBlameTree::Delta(mut delta) => {
+                Self::intern_paths(&self.paths, &mut delta.changed, delta.classes.as_mut());
+                for path in &mut delta.removed {
+                    self.paths.intern(path);
+                }
+                Ok(Some(delta))
+            }
+        }
+    }
+
+    /// Save a blametree unless it already exists.
+    pub fn save_blametree(
+        &mut self,
+        blametree: &BlameTree,
+        parent: Option<Oid>,
+    ) -> anyhow::Result<()> {
+        if self.blametree_exists(blametree.commit)? {
+            return Ok(());
+        }
+        self.overwrite_blametree(blametree, parent)
+    }
+
+    /// Save a blametree, as a delta to its parent's blametree if possible.
+    pub fn overwrite_blametree(
+        &mut self,
+        blametree: &BlameTree,
+        parent: Option<Oid>,
+    ) -> anyhow::Result<()> {
+        let delta = match parent {
+            Some(parent) if self.blametree_exists(parent)? => {
+                let key = parent.to_string();
+                let depth = match Self::load_data(&self.store, Kind::BlameTree, &key)? {
+                    StoredBlameTree::Full(_) => 1,
+                    StoredBlameTree::Delta(delta) => delta.depth + 1,
+                };
+                let parent = self.load_blametree_cached(parent)?;
+                let delta = BlameTreeDelta::new(&parent, depth, blametree);
+
+                // Deltas of completely different trees aren't worth it.
+                let small = delta.removed.len() + delta.changed.len() < blametree.blames.len() / 2;
+                (depth <= MAX_DELTA_DEPTH && small).then_some(delta)
+            }
+            _ => None,
+        };
+
+        let stored = match delta {
+            Some(delta) => StoredBlameTree::Delta(delta),
+            None => StoredBlameTree::Full(blametree.clone()),
+        };
+        self.save_data(Kind::BlameTree, &blametree.commit.to_string(), &stored)
+    }
+
+    /// Load precomputed counts, or `None` if they haven't been computed yet.
+    pub fn load_aggregate<T: DeserializeOwned>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        let Some(bytes) = self.store.get(Kind::Aggregate, key)? else {
+            return Ok(None);
+        };
+        let value =
+            bincode::deserialize(&bytes).context(format!("failed to load aggregate {key}"))?;
+        Ok(Some(value))
     }
 
-    pub fn load_blametree_uncached(&self, hash: String) -> anyhow::Result<BlameTree> {
-        let path = path_blametree(&self.dir, &hash);
-        Self::load_data_uncached(&path)
+    pub fn save_aggregate<T: Serialize>(&self, key: &str, value: &T) -> anyhow::Result<()> {
+        self.save_data(Kind::Aggregate, key, value)
     }
 
-    pub fn save_blametree(&self, blametree: &BlameTree) -> anyhow::Result<()> {
-        let path = path_blametree(&self.dir, &blametree.commit);
-        Self::save_data_without_overwriting(&path, blametree)
+    pub fn blame_exists(&self, id: &BlameId) -> anyhow::Result<bool> {
+        self.store.contains(Kind::Blame, &id.sha256().to_string())
     }
 
-    pub fn blame_exists(&self, id: &BlameId) -> bool {
-        path_blame(&self.dir, &id.sha256()).exists()
+    pub fn load_blame_cached(&self, id: &BlameId) -> anyhow::Result<Blame> {
+        let mut blame =
+            Self::load_data_cached(&self.store, &self.blame_cache, Kind::Blame, id.sha256())
+                .context(format!("blame of {}", id.path))?;
+        // Share the path with the blametree the id came from.
+        blame.id.path = id.path.clone();
+        Ok(blame)
     }
 
-    pub fn load_blame_cached(&mut self, id: &BlameId) -> anyhow::Result<Blame> {
-        let path = path_blame(&self.dir, &id.sha256());
-        Self::load_data_cached(&mut self.blame_cache, &path, id.sha256())
+    pub fn load_blame_uncached(&self, id: &BlameId) -> anyhow::Result<Blame> {
+        Self::load_data(&self.store, Kind::Blame, &id.sha256().to_string())
+            .context(format!("blame of {}", id.path))
     }
 
     pub fn save_blame(&self, blame: &Blame) -> anyhow::Result<()> {
-        let path = path_blame(&self.dir, &blame.id.sha256());
-        Self::save_data_without_overwriting(&path, blame)
+        self.save_data_without_overwriting(Kind::Blame, &blame.id.sha256().to_string(), blame)
     }
 }
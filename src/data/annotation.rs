@@ -0,0 +1,45 @@
+use jiff::civil::Date;
+use serde::{Deserialize, Serialize};
+
+/// An event to mark in graphs, either at a commit (or a prefix of its hash) or
+/// at a date.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub commit: Option<String>,
+    pub date: Option<Date>,
+    pub label: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Contents of `annotations.toml`.
+///
+/// ```toml
+/// [[annotations]]
+/// commit = "0123abcd"
+/// label = "Big refactor"
+/// description = "Moved everything into modules"
+///
+/// [[annotations]]
+/// date = "2023-05-01"
+/// label = "Reorg"
+/// ```
+#[derive(Default, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+impl Annotations {
+    pub fn check(&self) -> anyhow::Result<()> {
+        for annotation in &self.annotations {
+            if annotation.commit.is_some() == annotation.date.is_some() {
+                anyhow::bail!(
+                    "annotation {} needs exactly one of commit or date",
+                    annotation.label
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,18 +1,247 @@
 use std::collections::{HashMap, HashSet};
 
+use anyhow::Context;
+use globset::{GlobBuilder, GlobMatcher};
+use jiff::civil::Date;
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 
+/// Top-level keys of `authors.toml` that are not author names.
+const RESERVED_KEYS: &[&str] = &["rules", "bots", "groups", "ignore_case"];
+
+/// Identities that belong to bots, matched against names and emails.
+const BUILTIN_BOTS: &[&str] = &[
+    "*[bot]",
+    "*[bot]@*",
+    "dependabot*",
+    "renovate*",
+    "greenkeeper*",
+    "snyk-bot",
+    "semantic-release-bot",
+    "github-actions",
+    "pre-commit-ci*",
+    "allcontributors*",
+    "*@dependabot.com",
+    "bot@renovateapp.com",
+    // The committer of commits made through GitHub's web interface.
+    "web-flow",
+    "noreply@github.com",
+];
+
+/// A glob or regex matching names or emails.
+#[derive(Serialize, Deserialize)]
+pub struct Pattern {
+    pub glob: Option<String>,
+    pub regex: Option<String>,
+    #[serde(default)]
+    pub ignore_case: bool,
+}
+
+/// Rename every author matching a pattern.
+#[derive(Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(flatten)]
+    pub pattern: Pattern,
+    pub name: String,
+}
+
+enum Matcher {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(pattern: &Pattern) -> anyhow::Result<Self> {
+        match (&pattern.glob, &pattern.regex) {
+            (Some(glob), None) => {
+                let glob = GlobBuilder::new(glob)
+                    .case_insensitive(pattern.ignore_case)
+                    .literal_separator(false)
+                    .build()?;
+                Ok(Self::Glob(glob.compile_matcher()))
+            }
+            (None, Some(regex)) => {
+                let regex = RegexBuilder::new(&format!("^(?:{regex})$"))
+                    .case_insensitive(pattern.ignore_case)
+                    .build()?;
+                Ok(Self::Regex(regex))
+            }
+            _ => anyhow::bail!("patterns need exactly one of glob or regex"),
+        }
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob.is_match(name),
+            Self::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// A period during which an author belonged to a group. Both bounds are
+/// optional, `since` is inclusive and `until` is exclusive.
+#[derive(Serialize, Deserialize)]
+pub struct Period {
+    pub group: String,
+    pub since: Option<Date>,
+    pub until: Option<Date>,
+}
+
+impl Period {
+    fn contains(&self, date: Date) -> bool {
+        self.since.map_or(true, |since| since <= date)
+            && self.until.map_or(true, |until| date < until)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Membership {
+    Always(String),
+    Periods(Vec<Period>),
+}
+
+/// Contents of `authors.toml`.
+///
+/// Top-level keys rename authors. Authors without an exact match are renamed by
+/// the first matching entry in `rules`, if any. Regexes must match the entire
+/// name, and a rule never renames a name to itself. With `ignore_case`, exact
+/// entries match regardless of case and also never rename a name to itself.
+/// Authors named `rules`, `bots`, `groups` or `ignore_case` can only be
+/// renamed by rules. Tables in `groups` assign
+/// authors to groups like teams or companies, optionally limited to certain
+/// periods. Entries in `bots` mark identities as bots, in addition to a few
+/// well-known bots:
+///
+/// ```toml
+/// ignore_case = true
+/// "jdoe" = "Jane Doe"
+///
+/// [[rules]]
+/// glob = "*@bots.example.com"
+/// name = "Bots"
+///
+/// [[rules]]
+/// regex = 'dependabot(\[bot\]|-preview)?'
+/// ignore_case = true
+/// name = "Dependabot"
+///
+/// [[bots]]
+/// glob = "release-bot@example.com"
+///
+/// [groups.team]
+/// "Bob" = "Backend"
+/// "Jane Doe" = [
+///     { group = "Frontend", until = "2022-03-01" },
+///     { group = "Backend", since = "2022-03-01" },
+/// ]
+/// ```
 #[derive(Default, Serialize, Deserialize)]
-pub struct Authors(HashMap<String, String>);
+pub struct Authors {
+    #[serde(default)]
+    ignore_case: bool,
+    #[serde(default)]
+    rules: Vec<Rule>,
+    #[serde(skip)]
+    matchers: Vec<Matcher>,
+    #[serde(default)]
+    bots: Vec<Pattern>,
+    #[serde(skip)]
+    bot_matchers: Vec<Matcher>,
+    #[serde(default)]
+    groups: HashMap<String, HashMap<String, Membership>>,
+    #[serde(flatten)]
+    aliases: HashMap<String, String>,
+}
 
 impl Authors {
+    /// Parse the contents of `authors.toml`.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let table = toml::from_str::<toml::Table>(s)?;
+        for key in RESERVED_KEYS {
+            if table.get(*key).is_some_and(|v| v.is_str()) {
+                anyhow::bail!("{key:?} can't be renamed by a top-level key, use a rule instead");
+            }
+        }
+        Ok(table.try_into()?)
+    }
+
+    /// Whether the name can be renamed by a top-level key.
+    pub fn is_reserved(name: &str) -> bool {
+        RESERVED_KEYS.contains(&name)
+    }
+
+    /// Compile the rules. Must be called before using the rules.
+    pub fn compile(&mut self) -> anyhow::Result<()> {
+        if self.ignore_case {
+            let mut aliases = HashMap::new();
+            for (name, canonical) in self.aliases.drain() {
+                let key = name.to_lowercase();
+                if let Some(other) = aliases.get(&key).filter(|other| **other != canonical) {
+                    anyhow::bail!("{name} is renamed to both {other} and {canonical}");
+                }
+                aliases.insert(key, canonical);
+            }
+            self.aliases = aliases;
+        }
+
+        self.matchers = self
+            .rules
+            .iter()
+            .map(|r| Matcher::new(&r.pattern))
+            .collect::<anyhow::Result<_>>()
+            .context("invalid pattern in rules")?;
+
+        let builtin_bots = BUILTIN_BOTS.iter().map(|glob| Pattern {
+            glob: Some(glob.to_string()),
+            regex: None,
+            ignore_case: true,
+        });
+        self.bot_matchers = builtin_bots
+            .map(|p| Matcher::new(&p))
+            .chain(self.bots.iter().map(Matcher::new))
+            .collect::<anyhow::Result<_>>()
+            .context("invalid pattern in bots")?;
+
+        Ok(())
+    }
+
+    pub fn is_bot(&self, name: &str) -> bool {
+        self.bot_matchers.iter().any(|m| m.is_match(name))
+    }
+
+    fn next(&self, name: &str) -> Option<&str> {
+        if self.ignore_case {
+            match self.aliases.get(&name.to_lowercase()) {
+                Some(next) if next != name => return Some(next),
+                _ => {}
+            }
+        } else if let Some(next) = self.aliases.get(name) {
+            return Some(next);
+        }
+
+        self.rules
+            .iter()
+            .zip(&self.matchers)
+            .find(|(rule, matcher)| rule.name != name && matcher.is_match(name))
+            .map(|(rule, _)| &rule.name as &str)
+    }
+
+    /// Every loop must pass through the target of an alias or rule, so it is
+    /// enough to start searching from those.
     pub fn check_for_cycles(&self) -> anyhow::Result<()> {
-        for start in self.0.keys() {
-            let mut cur = start;
+        let starts = self
+            .aliases
+            .keys()
+            .chain(self.aliases.values())
+            .chain(self.rules.iter().map(|r| &r.name));
+
+        for start in starts {
+            let mut cur = start as &str;
             let mut seen = HashSet::new();
             seen.insert(cur);
 
-            while let Some(next) = self.0.get(cur) {
+            while let Some(next) = self.next(cur) {
                 if seen.contains(next) {
                     anyhow::bail!("author loop detected containing {next}");
                 }
@@ -24,11 +253,34 @@ impl Authors {
         Ok(())
     }
 
+    pub fn insert(&mut self, name: String, canonical: String) {
+        let name = match self.ignore_case {
+            true => name.to_lowercase(),
+            false => name,
+        };
+        self.aliases.insert(name, canonical);
+    }
+
     pub fn get(&self, name: &str) -> String {
         let mut name = name;
-        while let Some(next_name) = self.0.get(name) {
+        while let Some(next_name) = self.next(name) {
             name = next_name;
         }
         name.to_string()
     }
+
+    pub fn has_groups(&self, dimension: &str) -> bool {
+        self.groups.contains_key(dimension)
+    }
+
+    /// The group an author belonged to on a certain date.
+    pub fn group(&self, dimension: &str, author: &str, date: Date) -> Option<&str> {
+        match self.groups.get(dimension)?.get(author)? {
+            Membership::Always(group) => Some(group),
+            Membership::Periods(periods) => periods
+                .iter()
+                .find(|p| p.contains(date))
+                .map(|p| &p.group as &str),
+        }
+    }
 }
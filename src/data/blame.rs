@@ -1,41 +1,150 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
 
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use sha2::Digest;
+
+use super::{Oid, Sha256};
 
 /// A unique identifier for the blame of a single file. Can be converted to a
-/// file name.Multiple commits may share a blame in certain situations.
+/// file name. Multiple commits may share a blame in certain situations.
 #[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BlameId {
-    pub commit: String,
-    pub blob: String,
-    pub path: String,
+    pub commit: Oid,
+    pub blob: Oid,
+    pub path: Arc<str>,
 }
 
 impl BlameId {
-    pub fn sha256(&self) -> String {
-        let mut hasher = Sha256::new();
+    pub fn sha256(&self) -> Sha256 {
+        let mut hasher = sha2::Sha256::new();
 
         // The commit and blob always have the same length, so I don't need any
         // sort of separator character between any of the fields to ensure that
         // different blame ids can't be confused.
-        hasher.update(&self.commit);
-        hasher.update(&self.blob);
-        hasher.update(&self.path);
+        hasher.update(self.commit.as_bytes());
+        hasher.update(self.blob.as_bytes());
+        hasher.update(self.path.as_bytes());
 
-        let hash = hasher.finalize();
-        format!("{hash:x}")
+        Sha256(hasher.finalize().into())
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Blame {
     pub id: BlameId,
-    pub lines_by_commit: HashMap<String, u64>,
+    pub lines_by_commit: HashMap<Oid, u64>,
+}
+
+/// Files that are usually not interesting when looking at who wrote a project.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileClass {
+    Vendored,
+    Generated,
+    Binary,
+}
+
+impl fmt::Display for FileClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Vendored => write!(f, "vendored"),
+            Self::Generated => write!(f, "generated"),
+            Self::Binary => write!(f, "binary"),
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct BlameTree {
-    pub commit: String,
+    pub commit: Oid,
     pub blames: Vec<BlameId>,
+    /// Class of every file that isn't a regular source file, by path.
+    pub classes: HashMap<Arc<str>, FileClass>,
+}
+
+/// The changes to a blametree compared to the blametree of a parent commit.
+#[derive(Serialize, Deserialize)]
+pub struct BlameTreeDelta {
+    pub commit: Oid,
+    pub parent: Oid,
+    /// Number of deltas between this and the nearest full blametree.
+    pub depth: u32,
+    /// Paths of files that no longer exist.
+    pub removed: Vec<Arc<str>>,
+    /// Blame ids of files that are new or changed.
+    pub changed: Vec<BlameId>,
+    /// All file classes, if they differ from the parent's.
+    pub classes: Option<HashMap<Arc<str>, FileClass>>,
+}
+
+impl BlameTreeDelta {
+    pub fn new(parent: &BlameTree, depth: u32, blametree: &BlameTree) -> Self {
+        let parent_blames = parent
+            .blames
+            .iter()
+            .map(|b| (&b.path, b))
+            .collect::<HashMap<_, _>>();
+        let paths = blametree
+            .blames
+            .iter()
+            .map(|b| &b.path)
+            .collect::<HashSet<_>>();
+
+        Self {
+            commit: blametree.commit,
+            parent: parent.commit,
+            depth,
+            removed: parent
+                .blames
+                .iter()
+                .filter(|b| !paths.contains(&b.path))
+                .map(|b| b.path.clone())
+                .collect(),
+            changed: blametree
+                .blames
+                .iter()
+                .filter(|b| parent_blames.get(&b.path) != Some(b))
+                .cloned()
+                .collect(),
+            classes: (blametree.classes != parent.classes).then(|| blametree.classes.clone()),
+        }
+    }
+
+    /// Reconstruct the blametree from the parent's blametree. New files are
+    /// appended after the files that already existed in the parent.
+    pub fn apply(self, parent: BlameTree) -> BlameTree {
+        let removed = self.removed.into_iter().collect::<HashSet<_>>();
+        let mut changed = self
+            .changed
+            .into_iter()
+            .map(|b| (b.path.clone(), b))
+            .collect::<HashMap<_, _>>();
+
+        let mut blames = parent
+            .blames
+            .into_iter()
+            .filter(|b| !removed.contains(&b.path))
+            .map(|b| changed.remove(&b.path).unwrap_or(b))
+            .collect::<Vec<_>>();
+        let mut added = changed.into_values().collect::<Vec<_>>();
+        added.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        blames.extend(added);
+
+        BlameTree {
+            commit: self.commit,
+            blames,
+            classes: self.classes.unwrap_or(parent.classes),
+        }
+    }
+}
+
+/// How blametrees are stored. Most commits only change a few files, so storing
+/// the difference to the first parent's blametree saves a lot of space.
+#[derive(Serialize, Deserialize)]
+pub enum StoredBlameTree {
+    Full(BlameTree),
+    Delta(BlameTreeDelta),
 }
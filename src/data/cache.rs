@@ -0,0 +1,206 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash},
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use lru::LruCache;
+
+use super::{Blame, BlameId, BlameTree, Commit, FileClass, Oid, Sha256};
+
+/// Number of separately locked parts of a [`ShardedCache`].
+const SHARDS: usize = 16;
+
+/// Estimated bookkeeping per cache entry, like the LRU list and hash table.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Memory used by a value beyond its own size, to keep caches within their
+/// memory budget. Estimates are fine.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! no_heap_size {
+    ($($ty:ty),*) => {
+        $(
+            impl HeapSize for $ty {
+                fn heap_size(&self) -> usize {
+                    0
+                }
+            }
+        )*
+    };
+}
+
+// Paths are interned, so they are not counted per blame id.
+no_heap_size!(u64, Oid, Sha256, FileClass, BlameId, Arc<str>);
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * mem::size_of::<T>() + self.iter().map(T::heap_size).sum::<usize>()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize> HeapSize for HashMap<K, V> {
+    fn heap_size(&self) -> usize {
+        // One control byte per bucket.
+        let buckets = self.capacity() * (mem::size_of::<(K, V)>() + 1);
+        buckets
+            + self
+                .iter()
+                .map(|(k, v)| k.heap_size() + v.heap_size())
+                .sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Arc<T> {
+    fn heap_size(&self) -> usize {
+        mem::size_of::<T>() + (**self).heap_size()
+    }
+}
+
+impl HeapSize for Commit {
+    fn heap_size(&self) -> usize {
+        self.parents.heap_size()
+            + self.author.heap_size()
+            + self.author_mail.heap_size()
+            + self.committer.heap_size()
+            + self.committer_mail.heap_size()
+            + self.subject.heap_size()
+            + self.trailers.heap_size()
+    }
+}
+
+impl HeapSize for BlameTree {
+    fn heap_size(&self) -> usize {
+        self.blames.heap_size() + self.classes.heap_size()
+    }
+}
+
+impl HeapSize for Blame {
+    fn heap_size(&self) -> usize {
+        self.lines_by_commit.heap_size()
+    }
+}
+
+/// How well a cache worked.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+struct Shard<K, V> {
+    /// Entries with their estimated size.
+    entries: LruCache<K, (V, usize)>,
+    bytes: usize,
+}
+
+/// An LRU cache that can be used from multiple threads at once and holds
+/// entries up to an estimated total size.
+///
+/// Entries are spread over separately locked LRU caches by the hash of their
+/// key, so threads rarely wait for each other. Each shard gets an equal part
+/// of the memory budget and evicts its own least recently used entries.
+pub struct ShardedCache<K, V> {
+    hasher: RandomState,
+    shards: Vec<Mutex<Shard<K, V>>>,
+    shard_bytes: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Hash + Eq + HeapSize, V: Clone + HeapSize> ShardedCache<K, V> {
+    /// A cache using roughly `bytes` bytes of memory.
+    pub fn new(bytes: u64) -> Self {
+        let bytes = usize::try_from(bytes).unwrap_or(usize::MAX);
+        Self {
+            hasher: RandomState::new(),
+            shards: (0..SHARDS)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        entries: LruCache::unbounded(),
+                        bytes: 0,
+                    })
+                })
+                .collect(),
+            shard_bytes: bytes / SHARDS,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<Shard<K, V>> {
+        let hash = self.hasher.hash_one(key);
+        &self.shards[hash as usize % SHARDS]
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = (self.shard(key).lock().unwrap().entries)
+            .get(key)
+            .map(|(value, _)| value.clone());
+        let counter = match value {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    pub fn put(&self, key: K, value: V) {
+        let size = mem::size_of::<(K, V)>() + key.heap_size() + value.heap_size() + ENTRY_OVERHEAD;
+        let mut shard = self.shard(&key).lock().unwrap();
+        if let Some((_, (_, replaced))) = shard.entries.push(key, (value, size)) {
+            shard.bytes -= replaced;
+        }
+        shard.bytes += size;
+
+        while shard.bytes > self.shard_bytes {
+            let Some((_, (_, evicted))) = shard.entries.pop_lru() else {
+                break;
+            };
+            shard.bytes -= evicted;
+        }
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            shard.entries.clear();
+            shard.bytes = 0;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: 0,
+            bytes: 0,
+        };
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            stats.entries += shard.entries.len() as u64;
+            stats.bytes += shard.bytes as u64;
+        }
+        stats
+    }
+}
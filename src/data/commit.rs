@@ -1,15 +1,42 @@
-use jiff::Timestamp;
+use jiff::{civil::DateTime, tz::Offset, Timestamp};
 use serde::{Deserialize, Serialize};
 
+use super::Oid;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Commit {
-    pub hash: String,
-    pub parents: Vec<String>,
+    pub hash: Oid,
+    pub parents: Vec<Oid>,
     pub author: String,
     pub author_mail: String,
     pub author_time: Timestamp,
+    /// The author's UTC offset in seconds at the time of the commit.
+    pub author_offset: i32,
     pub committer: String,
     pub committer_mail: String,
     pub committer_time: Timestamp,
     pub subject: String,
+    /// Trailers from the end of the commit message as key-value pairs.
+    pub trailers: Vec<(String, String)>,
+}
+
+impl Commit {
+    /// The author date in the author's own time zone.
+    pub fn author_local_time(&self) -> DateTime {
+        let offset = Offset::from_seconds(self.author_offset).unwrap_or(Offset::UTC);
+        offset.to_datetime(self.author_time)
+    }
+
+    /// Name and email of every `Co-authored-by` trailer.
+    pub fn co_authors(&self) -> Vec<(&str, &str)> {
+        self.trailers
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case("co-authored-by"))
+            .filter_map(|(_, v)| {
+                let (name, email) = v.split_once('<')?;
+                let email = email.split_once('>')?.0;
+                Some((name.trim(), email.trim()))
+            })
+            .collect()
+    }
 }
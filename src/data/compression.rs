@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+use zstd::{
+    dict::{DecoderDictionary, EncoderDictionary},
+    stream::{read::Decoder, write::Encoder},
+};
+
+use super::{Kind, Store};
+
+const LEVEL: i32 = 3;
+
+/// Upper bound for the size of a trained dictionary.
+const DICTIONARY_SIZE: usize = 112 * 1024;
+
+/// Dictionaries trained on fewer objects don't help much.
+const MIN_SAMPLES: usize = 100;
+
+/// Most objects a dictionary is trained on.
+pub const MAX_SAMPLES: usize = 2000;
+
+// Every compressed object starts with one of these, followed by a zstd frame.
+const PLAIN: u8 = 0;
+const WITH_DICTIONARY: u8 = 1;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+fn dictionary_key(kind: Kind) -> String {
+    format!("dictionary-{kind}")
+}
+
+/// Whether the bytes are a compressed object, as opposed to plain bincode from
+/// before objects were compressed.
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.len() >= 5 && bytes[0] <= WITH_DICTIONARY && bytes[1..5] == ZSTD_MAGIC
+}
+
+struct Dictionary {
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+    /// Retired dictionaries are only used for decompressing.
+    retired: bool,
+}
+
+impl Dictionary {
+    fn new(bytes: &[u8]) -> Self {
+        Self {
+            encoder: EncoderDictionary::copy(bytes, LEVEL),
+            decoder: DecoderDictionary::copy(bytes),
+            retired: false,
+        }
+    }
+}
+
+/// Compresses objects with zstd before storing them, using a dictionary
+/// trained on objects of the same kind once one exists. Meta objects are
+/// stored as they are.
+///
+/// Dictionaries are never replaced since the objects compressed with them
+/// would become unreadable, unless all objects are rewritten anyways. See
+/// [`Self::retire_dictionaries`].
+pub struct Compressed {
+    store: Box<dyn Store>,
+    dictionaries: HashMap<Kind, Dictionary>,
+}
+
+impl Compressed {
+    pub fn open(store: Box<dyn Store>) -> anyhow::Result<Self> {
+        let mut dictionaries = HashMap::new();
+        for kind in [Kind::Commit, Kind::BlameTree, Kind::Blame] {
+            if let Some(bytes) = store.get(Kind::Meta, &dictionary_key(kind))? {
+                dictionaries.insert(kind, Dictionary::new(&bytes));
+            }
+        }
+        Ok(Self {
+            store,
+            dictionaries,
+        })
+    }
+
+    pub fn store(&self) -> &dyn Store {
+        &*self.store
+    }
+
+    pub fn into_store(self) -> Box<dyn Store> {
+        self.store
+    }
+
+    pub fn has_dictionary(&self, kind: Kind) -> bool {
+        self.dictionaries.contains_key(&kind)
+    }
+
+    /// Stop compressing objects with the current dictionaries. They can still
+    /// be decompressed until [`Self::remove_retired_dictionaries`] is called.
+    pub fn retire_dictionaries(&mut self) {
+        for dictionary in self.dictionaries.values_mut() {
+            dictionary.retired = true;
+        }
+    }
+
+    /// Remove retired dictionaries so new ones can be trained. Objects still
+    /// compressed with them become unreadable.
+    pub fn remove_retired_dictionaries(&mut self) -> anyhow::Result<()> {
+        for kind in Kind::ALL {
+            if self.dictionaries.get(&kind).is_some_and(|d| d.retired) {
+                self.store.remove(Kind::Meta, &dictionary_key(kind))?;
+                self.dictionaries.remove(&kind);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a stored object was compressed without a dictionary.
+    pub fn lacks_dictionary(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&PLAIN)
+    }
+
+    fn compress(&self, kind: Kind, bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if kind == Kind::Meta {
+            return Ok(bytes.to_vec());
+        }
+
+        match self.dictionaries.get(&kind).filter(|d| !d.retired) {
+            Some(dictionary) => {
+                let mut encoder =
+                    Encoder::with_prepared_dictionary(vec![WITH_DICTIONARY], &dictionary.encoder)?;
+                encoder.write_all(bytes)?;
+                Ok(encoder.finish()?)
+            }
+            None => {
+                let mut compressed = vec![PLAIN];
+                zstd::stream::copy_encode(bytes, &mut compressed, LEVEL)?;
+                Ok(compressed)
+            }
+        }
+    }
+
+    pub fn decompress(&self, kind: Kind, bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if kind == Kind::Meta {
+            return Ok(bytes);
+        }
+
+        let mut decompressed = vec![];
+        match bytes.first() {
+            Some(&PLAIN) => zstd::stream::copy_decode(&bytes[1..], &mut decompressed)?,
+            Some(&WITH_DICTIONARY) => {
+                let Some(dictionary) = self.dictionaries.get(&kind) else {
+                    anyhow::bail!("{kind} dictionary is missing");
+                };
+                Decoder::with_prepared_dictionary(&bytes[1..], &dictionary.decoder)?
+                    .read_to_end(&mut decompressed)?;
+            }
+            _ => anyhow::bail!("unknown compression"),
+        }
+        Ok(decompressed)
+    }
+
+    pub fn get(&self, kind: Kind, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.store.get(kind, key)? {
+            Some(bytes) => Ok(Some(self.decompress(kind, bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn contains(&self, kind: Kind, key: &str) -> anyhow::Result<bool> {
+        self.store.contains(kind, key)
+    }
+
+    pub fn put(&self, kind: Kind, key: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        self.store.put(kind, key, &self.compress(kind, bytes)?)
+    }
+
+    pub fn flush(&self) -> anyhow::Result<()> {
+        self.store.flush()
+    }
+
+    /// Train and save a dictionary for a kind of object from uncompressed
+    /// samples. Returns whether a dictionary was trained.
+    pub fn train_dictionary(&mut self, kind: Kind, samples: &[Vec<u8>]) -> anyhow::Result<bool> {
+        if kind == Kind::Meta || self.has_dictionary(kind) || samples.len() < MIN_SAMPLES {
+            return Ok(false);
+        }
+
+        // Training fails if the samples are too small or too uniform, in which
+        // case a dictionary wouldn't help anyways.
+        let Ok(bytes) = zstd::dict::from_samples(samples, DICTIONARY_SIZE) else {
+            return Ok(false);
+        };
+
+        self.store.put(Kind::Meta, &dictionary_key(kind), &bytes)?;
+        self.dictionaries.insert(kind, Dictionary::new(&bytes));
+        Ok(true)
+    }
+}
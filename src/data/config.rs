@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+use super::Backend;
+
+/// Contents of `config.toml`, defaults for command line options.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Config {
+    /// IANA time zone name, used when `--tz` is not given.
+    pub tz: Option<String>,
+    /// Backend for new data directories. Existing ones keep their backend
+    /// until migrated.
+    pub store: Option<Backend>,
+    /// Memory to use for caches, used when `--memory` is not given.
+    pub memory: Option<Memory>,
+}
+
+/// An amount of memory in the config file, either a number of bytes or a
+/// string in the format of [`parse_memory`].
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Memory {
+    Bytes(u64),
+    Text(String),
+}
+
+impl Memory {
+    pub fn bytes(&self) -> anyhow::Result<u64> {
+        match self {
+            Self::Bytes(bytes) => Ok(*bytes),
+            Self::Text(text) => parse_memory(text),
+        }
+    }
+}
+
+/// Parse an amount of memory like `512MiB`, `1.5GB` or `1048576`. Units
+/// without an `i`, like `MB`, are powers of 1000, single letters like `M` are
+/// powers of 1024.
+pub fn parse_memory(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => anyhow::bail!("unknown unit {unit:?} in {s:?}"),
+    };
+    let Ok(number) = number.parse::<f64>() else {
+        anyhow::bail!("invalid amount of memory {s:?}");
+    };
+    Ok((number * factor as f64) as u64)
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+fn parse_hex<const N: usize>(s: &str) -> anyhow::Result<[u8; N]> {
+    if s.len() != 2 * N || !s.is_ascii() {
+        anyhow::bail!("expected {} hex digits, got {s:?}", 2 * N);
+    }
+
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16)
+            .map_err(|_| anyhow::anyhow!("invalid hex digits in {s:?}"))?;
+    }
+    Ok(bytes)
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{byte:02x}")?;
+    }
+    Ok(())
+}
+
+/// Defines a fixed-size binary id that is shown and parsed as hex. It is
+/// stored as raw bytes in binary formats and as hex in human-readable ones.
+macro_rules! binary_id {
+    ($name:ident, $len:literal) => {
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub [u8; $len]);
+
+        impl FromStr for $name {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Ok(Self(parse_hex(s)?))
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write_hex(f, &self.0)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write_hex(f, &self.0)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if serializer.is_human_readable() {
+                    serializer.collect_str(self)
+                } else {
+                    self.0.serialize(serializer)
+                }
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                if deserializer.is_human_readable() {
+                    let s = String::deserialize(deserializer)?;
+                    s.parse().map_err(de::Error::custom)
+                } else {
+                    Ok(Self(<[u8; $len]>::deserialize(deserializer)?))
+                }
+            }
+        }
+    };
+}
+
+/// A git object id, for example of a commit or blob. Repositories use either
+/// SHA-1 or SHA-256 ids, never both. Like [`Sha256`], it is shown and parsed
+/// as hex and stored as raw bytes in binary formats, preceded by their length.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Oid {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl Oid {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Sha1(bytes) => bytes,
+            Self::Sha256(bytes) => bytes,
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        if let Ok(bytes) = bytes.try_into() {
+            return Ok(Self::Sha1(bytes));
+        }
+        if let Ok(bytes) = bytes.try_into() {
+            return Ok(Self::Sha256(bytes));
+        }
+        anyhow::bail!("expected 20 or 32 bytes, got {}", bytes.len());
+    }
+}
+
+impl FromStr for Oid {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.len() {
+            64 => Ok(Self::Sha256(parse_hex(s)?)),
+            _ => Ok(Self::Sha1(parse_hex(s)?)),
+        }
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_hex(f, self.as_bytes())
+    }
+}
+
+impl fmt::Debug for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_hex(f, self.as_bytes())
+    }
+}
+
+impl Serialize for Oid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(self.as_bytes())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Oid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            s.parse().map_err(de::Error::custom)
+        } else {
+            let bytes = <Vec<u8>>::deserialize(deserializer)?;
+            Self::from_bytes(&bytes).map_err(de::Error::custom)
+        }
+    }
+}
+
+// A SHA-256 hash identifying an object in the data directory.
+binary_id!(Sha256, 32);
+
+/// Deduplicates strings like paths, so that equal ones share an allocation.
+#[derive(Default)]
+pub struct Interner(Mutex<HashSet<Arc<str>>>);
+
+impl Interner {
+    pub fn intern(&self, s: &mut Arc<str>) {
+        let mut strings = self.0.lock().unwrap();
+        match strings.get(s) {
+            Some(interned) => *s = interned.clone(),
+            None => {
+                strings.insert(s.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oid_round_trips() {
+        for hex in [
+            "0123456789abcdef0123456789abcdef01234567",
+            "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        ] {
+            let oid = hex.parse::<Oid>().unwrap();
+            assert_eq!(oid.as_bytes().len() * 2, hex.len());
+            assert_eq!(oid.to_string(), hex);
+
+            let bytes = bincode::serialize(&oid).unwrap();
+            assert_eq!(bincode::deserialize::<Oid>(&bytes).unwrap(), oid);
+        }
+    }
+
+    #[test]
+    fn oid_rejects_other_lengths() {
+        assert!("0123456789abcdef".parse::<Oid>().is_err());
+        assert!(Oid::from_bytes(&[0; 16]).is_err());
+    }
+}
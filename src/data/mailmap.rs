@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+/// Replacement name and email, either of which may be missing.
+#[derive(Default)]
+struct Replacement {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+impl Replacement {
+    fn update(&mut self, name: Option<&str>, email: Option<&str>) {
+        if let Some(name) = name {
+            self.name = Some(name.to_string());
+        }
+        if let Some(email) = email {
+            self.email = Some(email.to_string());
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entry {
+    /// Used when only the email matches.
+    default: Replacement,
+    /// Used when both the email and the (lowercased) name match.
+    by_name: HashMap<String, Replacement>,
+}
+
+/// Split off the first `Name <email>` pair, returning the trimmed name, the
+/// email and the rest of the line.
+fn parse_name_and_email(line: &str) -> Option<(Option<&str>, &str, &str)> {
+    let (name, rest) = line.split_once('<')?;
+    let (email, rest) = rest.split_once('>')?;
+    let name = Some(name.trim()).filter(|n| !n.is_empty());
+    Some((name, email.trim(), rest))
+}
+
+/// A git mailmap, see gitmailmap(5).
+///
+/// Names and emails are matched case-insensitively, just like git does.
+#[derive(Default)]
+pub struct Mailmap(HashMap<String, Entry>);
+
+impl Mailmap {
+    pub fn parse(text: &str) -> Self {
+        let mut mailmap = Self::default();
+        for line in text.lines() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            let Some((name1, email1, rest)) = parse_name_and_email(line) else {
+                continue;
+            };
+
+            let (proper_name, proper_email, old_name, old_email) = match parse_name_and_email(rest)
+            {
+                Some((name2, email2, _)) => (name1, Some(email1), name2, email2),
+                None => (name1, None, None, email1),
+            };
+
+            let entry = mailmap.0.entry(old_email.to_lowercase()).or_default();
+            let replacement = match old_name {
+                Some(old_name) => entry.by_name.entry(old_name.to_lowercase()).or_default(),
+                None => &mut entry.default,
+            };
+            replacement.update(proper_name, proper_email);
+        }
+        mailmap
+    }
+
+    /// Map a name and email to their canonical versions.
+    pub fn get(&self, name: &str, email: &str) -> (String, String) {
+        let Some(entry) = self.0.get(&email.to_lowercase()) else {
+            return (name.to_string(), email.to_string());
+        };
+
+        let replacement = entry
+            .by_name
+            .get(&name.to_lowercase())
+            .unwrap_or(&entry.default);
+
+        let name = replacement.name.as_deref().unwrap_or(name);
+        let email = replacement.email.as_deref().unwrap_or(email);
+        (name.to_string(), email.to_string())
+    }
+}
@@ -0,0 +1,114 @@
+mod database;
+mod files;
+
+use std::{fmt, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+pub use self::{database::DatabaseStore, files::FileStore};
+
+/// The kinds of objects generated by blamegraph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// Objects that exist only once, like the log, keyed by their name.
+    Meta,
+    Commit,
+    BlameTree,
+    Blame,
+    /// Precomputed counts for a commit or blame under specific settings.
+    Aggregate,
+}
+
+impl Kind {
+    pub const ALL: [Self; 5] = [
+        Self::Meta,
+        Self::Commit,
+        Self::BlameTree,
+        Self::Blame,
+        Self::Aggregate,
+    ];
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Meta => write!(f, "meta"),
+            Self::Commit => write!(f, "commit"),
+            Self::BlameTree => write!(f, "blametree"),
+            Self::Blame => write!(f, "blame"),
+            Self::Aggregate => write!(f, "aggregate"),
+        }
+    }
+}
+
+/// How generated objects are stored in the data directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// One file per object, in fan-out directories.
+    Files,
+    /// A single embedded database file.
+    Database,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Files => write!(f, "files"),
+            Self::Database => write!(f, "database"),
+        }
+    }
+}
+
+/// Number and total size of the stored objects of a kind.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Usage {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+/// Storage for serialized objects, keyed by kind and name.
+pub trait Store: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    fn get(&self, kind: Kind, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    fn contains(&self, kind: Kind, key: &str) -> anyhow::Result<bool>;
+
+    fn put(&self, kind: Kind, key: &str, value: &[u8]) -> anyhow::Result<()>;
+
+    /// Remove an object if it exists.
+    fn remove(&self, kind: Kind, key: &str) -> anyhow::Result<()>;
+
+    fn keys(&self, kind: Kind) -> anyhow::Result<Vec<String>>;
+
+    fn usage(&self, kind: Kind) -> anyhow::Result<Usage>;
+
+    /// Ensure all previous writes are persisted.
+    fn flush(&self) -> anyhow::Result<()>;
+
+    /// Remove all objects.
+    fn destroy(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// Open the store in a data directory.
+///
+/// The configured backend is only used for data directories without any
+/// generated objects yet, so the backend of a data directory only changes by
+/// migrating it. Unless `create` is set, no database is created for such a
+/// directory, so commands that only read don't leave files behind.
+pub fn open(
+    dir: &Path,
+    configured: Option<Backend>,
+    create: bool,
+) -> anyhow::Result<Box<dyn Store>> {
+    if DatabaseStore::exists(dir) {
+        return Ok(Box::new(DatabaseStore::open(dir)?));
+    }
+
+    let files = FileStore::new(dir.to_path_buf());
+    if create && configured == Some(Backend::Database) && files.is_empty()? {
+        return Ok(Box::new(DatabaseStore::open(dir)?));
+    }
+    Ok(Box::new(files))
+}
@@ -0,0 +1,142 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use redb::{Database, Durability, ReadableTable, TableDefinition, TableError};
+
+use super::{Backend, Kind, Store, Usage};
+
+const META: TableDefinition<&str, &[u8]> = TableDefinition::new("meta");
+const COMMITS: TableDefinition<&str, &[u8]> = TableDefinition::new("commits");
+const BLAMETREES: TableDefinition<&str, &[u8]> = TableDefinition::new("blametrees");
+const BLAMES: TableDefinition<&str, &[u8]> = TableDefinition::new("blames");
+const AGGREGATES: TableDefinition<&str, &[u8]> = TableDefinition::new("aggregates");
+
+fn table(kind: Kind) -> TableDefinition<'static, &'static str, &'static [u8]> {
+    match kind {
+        Kind::Meta => META,
+        Kind::Commit => COMMITS,
+        Kind::BlameTree => BLAMETREES,
+        Kind::Blame => BLAMES,
+        Kind::Aggregate => AGGREGATES,
+    }
+}
+
+/// Stores all objects in a single transactional database file.
+pub struct DatabaseStore {
+    path: PathBuf,
+    db: Database,
+}
+
+impl DatabaseStore {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join("data.redb")
+    }
+
+    pub fn exists(dir: &Path) -> bool {
+        Self::path(dir).exists()
+    }
+
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        Self::open_at(Self::path(dir))
+    }
+
+    pub fn open_at(path: PathBuf) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let db = Database::create(&path)?;
+        Ok(Self { path, db })
+    }
+
+    /// Move the database file, for example to atomically replace another one.
+    pub fn rename(self, path: PathBuf) -> anyhow::Result<()> {
+        self.flush()?;
+        drop(self.db);
+        fs::rename(&self.path, path)?;
+        Ok(())
+    }
+}
+
+impl Store for DatabaseStore {
+    fn backend(&self) -> Backend {
+        Backend::Database
+    }
+
+    fn get(&self, kind: Kind, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let txn = self.db.begin_read()?;
+        let table = match txn.open_table(table(kind)) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => Err(e)?,
+        };
+        Ok(table.get(key)?.map(|v| v.value().to_vec()))
+    }
+
+    fn contains(&self, kind: Kind, key: &str) -> anyhow::Result<bool> {
+        Ok(self.get(kind, key)?.is_some())
+    }
+
+    fn put(&self, kind: Kind, key: &str, value: &[u8]) -> anyhow::Result<()> {
+        // Writes only become durable with the next flush, which is much faster
+        // than syncing every single object. The database stays consistent.
+        let mut txn = self.db.begin_write()?;
+        txn.set_durability(Durability::Eventual);
+        txn.open_table(table(kind))?.insert(key, value)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn remove(&self, kind: Kind, key: &str) -> anyhow::Result<()> {
+        let mut txn = self.db.begin_write()?;
+        txn.set_durability(Durability::Eventual);
+        txn.open_table(table(kind))?.remove(key)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn keys(&self, kind: Kind) -> anyhow::Result<Vec<String>> {
+        let txn = self.db.begin_read()?;
+        let table = match txn.open_table(table(kind)) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(vec![]),
+            Err(e) => Err(e)?,
+        };
+
+        let mut keys = vec![];
+        for entry in table.iter()? {
+            keys.push(entry?.0.value().to_string());
+        }
+        Ok(keys)
+    }
+
+    fn usage(&self, kind: Kind) -> anyhow::Result<Usage> {
+        let txn = self.db.begin_read()?;
+        let table = match txn.open_table(table(kind)) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(Usage::default()),
+            Err(e) => Err(e)?,
+        };
+
+        let mut usage = Usage::default();
+        for entry in table.iter()? {
+            usage.objects += 1;
+            usage.bytes += entry?.1.value().len() as u64;
+        }
+        Ok(usage)
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        let mut txn = self.db.begin_write()?;
+        txn.set_durability(Durability::Immediate);
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn destroy(self: Box<Self>) -> anyhow::Result<()> {
+        drop(self.db);
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
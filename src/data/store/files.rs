@@ -0,0 +1,157 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use tempfile::NamedTempFile;
+
+use super::{Backend, Kind, Store, Usage};
+
+const EXTENSION: &str = "bin";
+
+/// Stores every object in its own bincode file.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn kind_dir(&self, kind: Kind) -> PathBuf {
+        match kind {
+            Kind::Meta => self.dir.clone(),
+            Kind::Commit => self.dir.join("commits"),
+            Kind::BlameTree => self.dir.join("blametrees"),
+            Kind::Blame => self.dir.join("blames"),
+            Kind::Aggregate => self.dir.join("aggregates"),
+        }
+    }
+
+    fn path(&self, kind: Kind, key: &str) -> PathBuf {
+        let dir = self.kind_dir(kind);
+        match kind {
+            Kind::Meta => dir.join(key).with_extension(EXTENSION),
+            Kind::Commit | Kind::BlameTree | Kind::Blame | Kind::Aggregate => {
+                let first_two_chars = key.split_at(2).0;
+                dir.join(first_two_chars)
+                    .join(key)
+                    .with_extension(EXTENSION)
+            }
+        }
+    }
+
+    /// Whether no objects are stored yet, without listing all of them.
+    pub fn is_empty(&self) -> anyhow::Result<bool> {
+        for kind in [Kind::Commit, Kind::BlameTree, Kind::Blame, Kind::Aggregate] {
+            if self.kind_dir(kind).try_exists()? {
+                return Ok(false);
+            }
+        }
+        Ok(self.keys(Kind::Meta)?.is_empty())
+    }
+
+    fn keys_in(dir: &Path) -> anyhow::Result<Vec<String>> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => Err(e)?,
+        };
+
+        let mut keys = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == EXTENSION) && path.is_file() {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    keys.push(stem.to_string());
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
+
+impl Store for FileStore {
+    fn backend(&self) -> Backend {
+        Backend::Files
+    }
+
+    fn get(&self, kind: Kind, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(kind, key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    fn contains(&self, kind: Kind, key: &str) -> anyhow::Result<bool> {
+        Ok(self.path(kind, key).try_exists()?)
+    }
+
+    fn put(&self, kind: Kind, key: &str, value: &[u8]) -> anyhow::Result<()> {
+        let path = self.path(kind, key);
+        let parent = path.parent().unwrap();
+        fs::create_dir_all(parent)?;
+        let tmp_file = NamedTempFile::new_in(parent)?;
+        fs::write(tmp_file.path(), value)?;
+        tmp_file.persist(path)?;
+        Ok(())
+    }
+
+    fn remove(&self, kind: Kind, key: &str) -> anyhow::Result<()> {
+        match fs::remove_file(self.path(kind, key)) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    fn keys(&self, kind: Kind) -> anyhow::Result<Vec<String>> {
+        let dir = self.kind_dir(kind);
+        if kind == Kind::Meta {
+            return Self::keys_in(&dir);
+        }
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => Err(e)?,
+        };
+
+        let mut keys = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                keys.extend(Self::keys_in(&path)?);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn usage(&self, kind: Kind) -> anyhow::Result<Usage> {
+        let mut usage = Usage::default();
+        for key in self.keys(kind)? {
+            usage.objects += 1;
+            usage.bytes += fs::metadata(self.path(kind, &key))?.len();
+        }
+        Ok(usage)
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn destroy(self: Box<Self>) -> anyhow::Result<()> {
+        for key in self.keys(Kind::Meta)? {
+            fs::remove_file(self.path(Kind::Meta, &key))?;
+        }
+        for kind in [Kind::Commit, Kind::BlameTree, Kind::Blame, Kind::Aggregate] {
+            match fs::remove_dir_all(self.kind_dir(kind)) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+}
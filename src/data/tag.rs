@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+use super::Oid;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub name: String,
+    pub commit: Oid,
+}
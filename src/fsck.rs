@@ -0,0 +1,185 @@
+//! Check the generated objects for problems and remove the ones that are no
+//! longer needed.
+
+use std::collections::{HashMap, HashSet};
+
+use ignore::gitignore::Gitignore;
+
+use crate::{
+    data::{Data, Kind},
+    graph, progress,
+};
+
+/// Keys of all objects needed for stats about the commits in the log.
+///
+/// Blametrees are needed if they belong to a commit in the log or if another
+/// needed blametree is a delta to them. Blames are needed if a blametree of a
+/// commit in the log references them and their file isn't ignored by the
+/// top-level ignore file or by the one of any profile. Aggregates are needed
+/// if their commit is and they were computed with the user files of the top
+/// level or of any profile.
+#[derive(Default)]
+struct Reachable {
+    keys: HashMap<Kind, HashSet<String>>,
+    files_parts: HashSet<String>,
+    problems: Vec<String>,
+}
+
+impl Reachable {
+    fn insert(&mut self, kind: Kind, key: String) -> bool {
+        self.keys.entry(kind).or_default().insert(key)
+    }
+
+    fn contains(&self, kind: Kind, key: &str) -> bool {
+        if kind == Kind::Aggregate {
+            let mut parts = key.split('-');
+            let (Some(object), Some(files), Some(_), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return false;
+            };
+            return self.files_parts.contains(files) && self.contains(Kind::Commit, object);
+        }
+        self.keys.get(&kind).is_some_and(|k| k.contains(key))
+    }
+
+    fn check<T>(&mut self, result: anyhow::Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.problems.push(format!("{e:#}"));
+                None
+            }
+        }
+    }
+}
+
+/// Walk all objects reachable from the log. Blametrees are always loaded. If
+/// `verify` is set, tags, commits and blames are checked as well.
+fn find_reachable(data: &mut Data, verify: bool) -> anyhow::Result<Reachable> {
+    let ignores = data.load_all_ignores_uncached()?;
+    let log = data.load_log_uncached()?;
+
+    let mut reachable = Reachable {
+        files_parts: graph::current_files_parts(data)?,
+        ..Reachable::default()
+    };
+    if verify {
+        let in_log = log.iter().collect::<HashSet<_>>();
+        for tag in data.load_tags_uncached()? {
+            if !in_log.contains(&tag.commit) {
+                let problem = format!("tag {} points to a commit outside the log", tag.name);
+                reachable.problems.push(problem);
+            }
+        }
+    }
+
+    let pb = progress::counting_bar("Checking commits", log.len());
+    for &hash in &log {
+        reachable.insert(Kind::Commit, hash.to_string());
+        if verify {
+            reachable.check(data.load_commit_uncached(hash));
+        }
+
+        let problems = reachable.problems.len();
+        let mut next = Some(hash);
+        while let Some(hash) = next {
+            if !reachable.insert(Kind::BlameTree, hash.to_string()) {
+                break;
+            }
+            next = reachable.check(data.load_blametree_parent(hash)).flatten();
+        }
+
+        // Problems with the deltas would only be reported again.
+        let blametree = match reachable.problems.len() > problems {
+            true => None,
+            false => reachable.check(data.load_blametree_cached(hash)),
+        };
+        let Some(blametree) = blametree else {
+            pb.inc(1);
+            continue;
+        };
+        for blame_id in blametree.blames {
+            let is_ignored = |ignore: &Gitignore| {
+                ignore
+                    .matched_path_or_any_parents(&*blame_id.path, false)
+                    .is_ignore()
+            };
+            if ignores.iter().all(is_ignored) {
+                continue;
+            }
+
+            let key = blame_id.sha256().to_string();
+            if !reachable.insert(Kind::Blame, key) {
+                continue;
+            }
+            if verify {
+                reachable.check(data.load_blame_uncached(&blame_id));
+            }
+        }
+        pb.inc(1);
+    }
+    pb.finish();
+
+    Ok(reachable)
+}
+
+/// Keys of stored objects that aren't reachable, by kind.
+fn find_unreachable(
+    data: &Data,
+    reachable: &Reachable,
+) -> anyhow::Result<Vec<(Kind, Vec<String>)>> {
+    let mut result = vec![];
+    for kind in [Kind::Commit, Kind::BlameTree, Kind::Blame, Kind::Aggregate] {
+        let mut keys = data.store().keys(kind)?;
+        keys.retain(|key| !reachable.contains(kind, key));
+        result.push((kind, keys));
+    }
+    Ok(result)
+}
+
+pub fn fsck(data: &mut Data) -> anyhow::Result<()> {
+    let reachable = find_reachable(data, true)?;
+    let unreachable = find_unreachable(data, &reachable)?;
+
+    for problem in &reachable.problems {
+        println!("{problem}");
+    }
+
+    let unreachable = unreachable.iter().map(|(_, k)| k.len()).sum::<usize>();
+    if unreachable > 0 {
+        println!("Found {unreachable} unreachable objects, run gc to remove them");
+    }
+
+    match reachable.problems.len() {
+        0 => {
+            println!("No problems found");
+            Ok(())
+        }
+        n => anyhow::bail!("found {n} problems"),
+    }
+}
+
+pub fn gc(data: &mut Data) -> anyhow::Result<()> {
+    let reachable = find_reachable(data, false)?;
+
+    // Without all blametrees, there's no telling which blames are still
+    // needed.
+    if !reachable.problems.is_empty() {
+        anyhow::bail!(
+            "found {} problems, run fsck for details, nothing was removed",
+            reachable.problems.len()
+        );
+    }
+
+    for (kind, keys) in find_unreachable(data, &reachable)? {
+        let pb = progress::counting_bar(format!("Removing {kind}s"), keys.len());
+        for key in &keys {
+            data.store().remove(kind, key)?;
+            pb.inc(1);
+        }
+        pb.finish();
+    }
+
+    data.flush()
+}
@@ -1,18 +1,20 @@
 mod git;
+mod linguist;
 
 use std::{
     collections::{HashMap, HashSet},
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
 use anyhow::Context;
 use ignore::gitignore::Gitignore;
 use indicatif::{MultiProgress, ProgressDrawTarget};
+use linguist::Linguist;
 use rayon::iter::{ParallelBridge, ParallelIterator};
 
 use crate::{
-    data::{Blame, BlameId, BlameTree, Commit, Data},
+    data::{Blame, BlameId, BlameTree, Commit, Data, FileClass, Oid, Tag, FORMAT_VERSION},
     progress,
 };
 
@@ -35,33 +37,51 @@ fn save_commits(data: &Data, commits: &[Commit]) -> anyhow::Result<()> {
 
 fn save_log(data: &Data, commits: &[Commit]) -> anyhow::Result<()> {
     println!("Saving log");
-    let log = commits.iter().map(|c| c.hash.clone()).collect::<Vec<_>>();
+    let log = commits.iter().map(|c| c.hash).collect::<Vec<_>>();
     data.save_log(&log)?;
     Ok(())
 }
 
+fn save_tags(data: &Data, repo: &Path, commits: &[Commit]) -> anyhow::Result<()> {
+    println!("Saving tags");
+    let in_log = commits.iter().map(|c| c.hash).collect::<HashSet<_>>();
+    let tags = git::git_tags(repo)
+        .context("failed to list tags")?
+        .into_iter()
+        .filter(|(_, commit)| in_log.contains(commit))
+        .map(|(name, commit)| Tag { name, commit })
+        .collect::<Vec<_>>();
+    data.save_tags(&tags)?;
+    Ok(())
+}
+
 /// Find the earliest commit that a blame for this file can be computed in.
 ///
 /// Sharing blames across commits has a few subtle edge cases. Simplifying this
 /// logic is probably not possible without a big performance hit.
 fn find_blame_commit(
-    parents: &[HashMap<(String, String), String>],
-    key: &(String, String),
-) -> Option<String> {
+    parents: &[HashMap<(Arc<str>, Oid), Oid>],
+    key: &(Arc<str>, Oid),
+) -> Option<Oid> {
     let mut parents = parents.iter();
     let commit = parents.next()?.get(key)?;
     if parents.all(|p| p.get(key) == Some(commit)) {
-        Some(commit.clone())
+        Some(*commit)
     } else {
         None
     }
 }
 
-fn compute_blametree(data: &mut Data, repo: &Path, commit: &Commit) -> anyhow::Result<BlameTree> {
+fn compute_blametree(
+    data: &mut Data,
+    repo: &Path,
+    linguist: &mut Linguist,
+    commit: &Commit,
+) -> anyhow::Result<BlameTree> {
     let mut parents = vec![];
     for hash in &commit.parents {
         let by_path_and_blob = data
-            .load_blametree_cached(hash.clone())?
+            .load_blametree_cached(*hash)?
             .blames
             .into_iter()
             .map(|b| ((b.path, b.blob), b.commit))
@@ -71,33 +91,36 @@ fn compute_blametree(data: &mut Data, repo: &Path, commit: &Commit) -> anyhow::R
 
     let mut blames = vec![];
 
-    let files = git::git_ls_tree(repo, &commit.hash)?;
+    let files = git::git_ls_tree(repo, commit.hash)?;
+    let classes = linguist.classify(repo, &files)?;
     for (path, blob) in files {
         let key = (path, blob);
-        let commit = find_blame_commit(&parents, &key).unwrap_or_else(|| commit.hash.clone());
+        let commit = find_blame_commit(&parents, &key).unwrap_or(commit.hash);
         let (path, blob) = key;
         blames.push(BlameId { commit, blob, path });
     }
 
     Ok(BlameTree {
-        commit: commit.hash.clone(),
+        commit: commit.hash,
         blames,
+        classes,
     })
 }
 
 fn compute_blametrees(data: &mut Data, repo: &Path, commits: &[Commit]) -> anyhow::Result<()> {
     let pb = progress::counting_bar("Computing blametrees", commits.len());
+    let mut linguist = Linguist::new();
 
     // In topological order from parent to child, to ensure the blametrees of
     // all parents already exist when we get to a commit.
     for commit in commits.iter().rev() {
-        if data.blametree_exists(commit.hash.clone()) {
+        if data.blametree_exists(commit.hash)? {
             pb.inc(1);
             continue;
         }
 
-        let blametree = compute_blametree(data, repo, commit)?;
-        data.save_blametree(&blametree)?;
+        let blametree = compute_blametree(data, repo, &mut linguist, commit)?;
+        data.save_blametree(&blametree, commit.parents.first().copied())?;
         pb.inc(1);
     }
 
@@ -114,13 +137,13 @@ fn compute_blames_for_blametree(
     blametree: BlameTree,
 ) -> anyhow::Result<()> {
     let pb = mp.add(progress::commit_blame_bar(
-        &blametree.commit,
+        blametree.commit,
         blametree.blames.len(),
     ));
 
     for blame_id in blametree.blames {
         if ignore
-            .matched_path_or_any_parents(&blame_id.path, false)
+            .matched_path_or_any_parents(&*blame_id.path, false)
             .is_ignore()
         {
             continue;
@@ -131,12 +154,12 @@ fn compute_blames_for_blametree(
             continue;
         }
 
-        if data.blame_exists(&blame_id) {
+        if data.blame_exists(&blame_id)? {
             pb.inc(1);
             continue;
         }
 
-        let lines_by_commit = git::git_blame(repo, &blametree.commit, &blame_id.path)?;
+        let lines_by_commit = git::git_blame(repo, blametree.commit, &blame_id.path)?;
         data.save_blame(&Blame {
             id: blame_id,
             lines_by_commit,
@@ -165,7 +188,7 @@ fn compute_blames(
     let computed = Arc::new(Mutex::new(HashSet::<BlameId>::new()));
 
     commits.iter().par_bridge().try_for_each(|commit| {
-        let blametree = data.load_blametree_uncached(commit.hash.clone())?;
+        let blametree = data.load_blametree_uncached(commit.hash)?;
         compute_blames_for_blametree(data, repo, ignore, mp.clone(), computed.clone(), blametree)?;
         pb.inc(1);
         Ok::<_, anyhow::Error>(())
@@ -175,12 +198,64 @@ fn compute_blames(
     Ok(())
 }
 
-pub fn gather(data: &mut Data, repo: &Path) -> anyhow::Result<()> {
+fn save_mailmap(data: &Data, repo: &Path) -> anyhow::Result<()> {
+    println!("Saving mailmap");
+    match git::git_show_file(repo, "HEAD", ".mailmap")? {
+        Some(mailmap) => data.save_mailmap(&mailmap)?,
+        None => println!("Repository has no .mailmap"),
+    }
+    Ok(())
+}
+
+/// Information older versions of blamegraph didn't store, recovered from the
+/// repository the data was gathered from.
+pub struct Recovery {
+    repo: PathBuf,
+    commits: HashMap<Oid, Commit>,
+    linguist: Linguist,
+}
+
+impl Recovery {
+    pub fn new(repo: &Path) -> anyhow::Result<Self> {
+        let commits = search_for_commits(repo)?
+            .into_iter()
+            .map(|c| (c.hash, c))
+            .collect();
+        Ok(Self {
+            repo: repo.to_path_buf(),
+            commits,
+            linguist: Linguist::new(),
+        })
+    }
+
+    /// A commit as gathering would store it, if it is reachable from the HEAD.
+    pub fn commit(&self, hash: Oid) -> Option<&Commit> {
+        self.commits.get(&hash)
+    }
+
+    /// Classes of the files of a commit, given as a map from path to blob.
+    pub fn classify(
+        &mut self,
+        files: &HashMap<Arc<str>, Oid>,
+    ) -> anyhow::Result<HashMap<Arc<str>, FileClass>> {
+        self.linguist.classify(&self.repo, files)
+    }
+}
+
+pub fn gather(data: &mut Data, repo: &Path, mailmap: bool) -> anyhow::Result<()> {
     let ignore = data.load_ignore_uncached()?;
+    data.save_format_version(FORMAT_VERSION)?;
+    if mailmap {
+        save_mailmap(data, repo)?;
+    }
     let commits = search_for_commits(repo)?;
     save_commits(data, &commits)?;
     save_log(data, &commits)?;
+    save_tags(data, repo, &commits)?;
     compute_blametrees(data, repo, &commits)?;
     compute_blames(data, repo, &ignore, &commits)?;
+    println!("Training compression dictionaries");
+    data.train_dictionaries()?;
+    data.flush()?;
     Ok(())
 }
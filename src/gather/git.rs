@@ -1,14 +1,17 @@
 use std::{
     collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
     path::Path,
-    process::{Command, Output},
+    process::{Command, Output, Stdio},
     str::Lines,
+    sync::Arc,
+    thread,
 };
 
 use anyhow::Context;
-use jiff::Timestamp;
+use jiff::{civil::DateTime, tz::TimeZone, Timestamp};
 
-use crate::data::Commit;
+use crate::data::{Commit, Oid};
 
 fn stdout(output: Output) -> anyhow::Result<String> {
     if !output.status.success() {
@@ -28,23 +31,50 @@ fn stdout_lossy(output: Output) -> anyhow::Result<String> {
     Ok(stdout)
 }
 
-fn parse_rev_list_entry(lines: &mut Lines) -> Option<Commit> {
-    Some(Commit {
-        hash: lines.next()?.to_string(),
-        parents: lines
-            .next()?
-            .split(" ")
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string())
-            .collect::<Vec<_>>(),
-        author: lines.next()?.to_string(),
-        author_mail: lines.next()?.to_string(),
-        author_time: lines.next()?.parse::<Timestamp>().unwrap(),
-        committer: lines.next()?.to_string(),
-        committer_mail: lines.next()?.to_string(),
-        committer_time: lines.next()?.parse::<Timestamp>().unwrap(),
-        subject: lines.next()?.to_string(),
-    })
+/// Parse a strict ISO 8601 date into its timestamp and UTC offset in seconds.
+fn parse_iso_date(date: &str) -> (Timestamp, i32) {
+    let time = date.parse::<Timestamp>().unwrap();
+    let local = date[..19].parse::<DateTime>().unwrap();
+    let local = local.to_zoned(TimeZone::UTC).unwrap().timestamp();
+    let offset = (local.as_second() - time.as_second()).try_into().unwrap();
+    (time, offset)
+}
+
+fn parse_oid(s: &str) -> anyhow::Result<Oid> {
+    s.parse().context("git printed an unsupported object id")
+}
+
+fn parse_rev_list_entry(lines: &mut Lines) -> anyhow::Result<Option<Commit>> {
+    let Some(hash) = lines.next() else {
+        return Ok(None);
+    };
+    let hash = parse_oid(hash)?;
+    let mut next = || lines.next().context("incomplete rev-list entry");
+    let parents = next()?
+        .split(" ")
+        .filter(|s| !s.is_empty())
+        .map(parse_oid)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let author = next()?.to_string();
+    let author_mail = next()?.to_string();
+    let (author_time, author_offset) = parse_iso_date(next()?);
+    Ok(Some(Commit {
+        hash,
+        parents,
+        author,
+        author_mail,
+        author_time,
+        author_offset,
+        committer: next()?.to_string(),
+        committer_mail: next()?.to_string(),
+        committer_time: next()?.parse::<Timestamp>().unwrap(),
+        subject: next()?.to_string(),
+        trailers: next()?
+            .split('\x1f')
+            .filter_map(|t| t.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect(),
+    }))
 }
 
 pub fn git_rev_list(repo: &Path) -> anyhow::Result<Vec<Commit>> {
@@ -56,7 +86,10 @@ pub fn git_rev_list(repo: &Path) -> anyhow::Result<Vec<Commit>> {
         .arg("rev-list")
         .arg("--topo-order")
         .arg("--no-commit-header")
-        .arg("--format=tformat:%H%n%P%n%an%n%ae%n%aI%n%cn%n%ce%n%cI%n%s")
+        .arg(concat!(
+            "--format=tformat:%H%n%P%n%an%n%ae%n%aI%n%cn%n%ce%n%cI%n%s",
+            "%n%(trailers:only,unfold,separator=%x1F)",
+        ))
         .arg("HEAD")
         .output()?;
 
@@ -64,40 +97,94 @@ pub fn git_rev_list(repo: &Path) -> anyhow::Result<Vec<Commit>> {
 
     let stdout = stdout_lossy(output)?;
     let mut lines = stdout.lines();
-    while let Some(info) = parse_rev_list_entry(&mut lines) {
+    while let Some(info) = parse_rev_list_entry(&mut lines)? {
         result.push(info);
     }
 
     Ok(result)
 }
 
-pub fn git_ls_tree(repo: &Path, hash: &str) -> anyhow::Result<HashMap<String, String>> {
+/// Read a file from a commit, or `None` if it doesn't exist.
+pub fn git_show_file(repo: &Path, hash: &str, path: &str) -> anyhow::Result<Option<String>> {
+    // Unlike cat-file, ls-tree only fails for actual errors, like an invalid
+    // commit, and prints nothing if the path doesn't exist.
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("ls-tree")
+        .arg("--name-only")
+        .arg(hash)
+        .arg("--")
+        .arg(path)
+        .output()?;
+    if stdout(output)?.is_empty() {
+        return Ok(None);
+    }
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("cat-file")
+        .arg("blob")
+        .arg(format!("{hash}:{path}"))
+        .output()?;
+
+    Ok(Some(stdout(output)?))
+}
+
+/// List all tags as pairs of tag name and the commit the tag points to.
+pub fn git_tags(repo: &Path) -> anyhow::Result<Vec<(String, Oid)>> {
+    // Annotated tags need to be peeled to find their commit, which is what
+    // %(*objectname) does. For lightweight tags, it is empty.
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("for-each-ref")
+        .arg("--format=%(objectname) %(*objectname) %(refname:strip=2)")
+        .arg("refs/tags")
+        .output()?;
+
+    let tags = stdout(output)?
+        .lines()
+        .filter_map(|s| {
+            let (object, rest) = s.split_once(' ')?;
+            let (peeled, name) = rest.split_once(' ')?;
+            let commit = if peeled.is_empty() { object } else { peeled };
+            Some(parse_oid(commit).map(|commit| (name.to_string(), commit)))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(tags)
+}
+
+pub fn git_ls_tree(repo: &Path, hash: Oid) -> anyhow::Result<HashMap<Arc<str>, Oid>> {
     let output = Command::new("git")
         .arg("-C")
         .arg(repo)
         .arg("ls-tree")
         .arg("-r")
         .arg("--format=%(objectname) %(path)")
-        .arg(hash)
+        .arg(hash.to_string())
         .output()?;
 
     let files = stdout(output)?
         .lines()
         .map(|s| {
             let (blob, path) = s.split_once(' ').unwrap();
-            (path.to_string(), blob.to_string())
+            Ok((Arc::from(path), parse_oid(blob)?))
         })
-        .collect::<HashMap<_, _>>();
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
 
     Ok(files)
 }
 
-fn parse_blame_entry(lines: &mut Lines) -> Option<String> {
-    let first_line = lines.next()?;
+fn parse_blame_entry(lines: &mut Lines) -> anyhow::Result<Option<Oid>> {
+    let Some(first_line) = lines.next() else {
+        return Ok(None);
+    };
     assert!(!first_line.starts_with('\t'));
 
-    let hash = first_line.split(' ').next().unwrap().to_string();
-    assert!(hash.len() == 40);
+    let hash = parse_oid(first_line.split(' ').next().unwrap())?;
 
     // Skip remaining header lines and the line from the file
     for line in lines.by_ref() {
@@ -106,16 +193,16 @@ fn parse_blame_entry(lines: &mut Lines) -> Option<String> {
         }
     }
 
-    Some(hash)
+    Ok(Some(hash))
 }
 
-pub fn git_blame(repo: &Path, hash: &str, path: &str) -> anyhow::Result<HashMap<String, u64>> {
+pub fn git_blame(repo: &Path, hash: Oid, path: &str) -> anyhow::Result<HashMap<Oid, u64>> {
     let output = Command::new("git")
         .arg("-C")
         .arg(repo)
         .arg("blame")
         .arg("--porcelain")
-        .arg(hash)
+        .arg(hash.to_string())
         .arg("--")
         .arg(path)
         .output()?;
@@ -125,12 +212,71 @@ pub fn git_blame(repo: &Path, hash: &str, path: &str) -> anyhow::Result<HashMap<
         return Ok(HashMap::new());
     };
 
-    let mut count: HashMap<String, u64> = HashMap::new();
+    let mut count: HashMap<Oid, u64> = HashMap::new();
 
     let mut lines = stdout.lines();
-    while let Some(hash) = parse_blame_entry(&mut lines) {
+    while let Some(hash) = parse_blame_entry(&mut lines)? {
         *count.entry(hash).or_default() += 1;
     }
 
     Ok(count)
 }
+
+/// Read the first `max_len` bytes of each blob. Blobs that can't be found are
+/// omitted from the result.
+pub fn git_cat_blobs(
+    repo: &Path,
+    blobs: &[Oid],
+    max_len: usize,
+) -> anyhow::Result<HashMap<Oid, Vec<u8>>> {
+    if blobs.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("cat-file")
+        .arg("--batch")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Writing from a separate thread prevents a deadlock when both pipes fill
+    // up at the same time.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = blobs.iter().map(|b| format!("{b}\n")).collect::<String>();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let mut result = HashMap::new();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut header = String::new();
+    for _ in blobs {
+        header.clear();
+        stdout.read_line(&mut header)?;
+        let mut parts = header.split_whitespace();
+        let (Some(blob), Some(_kind), Some(size)) = (parts.next(), parts.next(), parts.next())
+        else {
+            // "<object> missing"
+            continue;
+        };
+        let size = size.parse::<u64>().context("invalid cat-file header")?;
+
+        let keep = size.min(max_len.try_into().unwrap_or(u64::MAX));
+        let mut content = vec![];
+        (&mut stdout).take(keep).read_to_end(&mut content)?;
+        io::copy(&mut (&mut stdout).take(size - keep), &mut io::sink())?;
+        result.insert(parse_oid(blob)?, content);
+
+        // Trailing newline after the content
+        stdout.read_exact(&mut [0])?;
+    }
+
+    writer.join().unwrap()?;
+    let status = child.wait()?;
+    if !status.success() {
+        anyhow::bail!("git cat-file exited with {status}");
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,308 @@
+//! Classify files as vendored, generated or binary, similar to what GitHub's
+//! linguist does.
+//!
+//! Explicit `linguist-vendored` and `linguist-generated` attributes from the
+//! `.gitattributes` files in a commit take precedence over the built-in path
+//! and content heuristics.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::data::{FileClass, Oid};
+
+use super::git;
+
+const VENDORED_PATHS: &[&str] = &[
+    "vendor/",
+    "vendors/",
+    "node_modules/",
+    "bower_components/",
+    "third_party/",
+    "third-party/",
+    "thirdparty/",
+    "3rdparty/",
+    "Godeps/_workspace/",
+    "Carthage/",
+    "Pods/",
+    ".yarn/releases/",
+    ".yarn/plugins/",
+    "*.min.js",
+    "*.min.css",
+];
+
+const GENERATED_PATHS: &[&str] = &[
+    // Lockfiles
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "bun.lockb",
+    "Gemfile.lock",
+    "composer.lock",
+    "poetry.lock",
+    "Pipfile.lock",
+    "uv.lock",
+    "go.sum",
+    "flake.lock",
+    "mix.lock",
+    "Podfile.lock",
+    "Package.resolved",
+    "packages.lock.json",
+    "pubspec.lock",
+    // Protobuf and gRPC
+    "*.pb.go",
+    "*.pb.cc",
+    "*.pb.h",
+    "*.pb.swift",
+    "*_pb2.py",
+    "*_pb2_grpc.py",
+    "*_pb.js",
+    "*_grpc_pb.js",
+];
+
+/// Markers in the first few lines of a file that indicate it was generated.
+const GENERATED_MARKERS: &[&str] = &[
+    "code generated by",
+    "do not edit",
+    "@generated",
+    "autogenerated",
+    "auto-generated",
+    "automatically generated",
+    "generated by the protocol buffer compiler",
+];
+
+const MARKER_LINES: usize = 5;
+
+/// Same heuristic git uses to detect binary files.
+const BINARY_PREFIX_LEN: usize = 8000;
+
+fn build_gitignore(lines: &[&str]) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(PathBuf::new());
+    for line in lines {
+        builder.add_line(None, line).unwrap();
+    }
+    builder.build().unwrap()
+}
+
+fn classify_content(prefix: &[u8]) -> Option<FileClass> {
+    if prefix.contains(&0) {
+        return Some(FileClass::Binary);
+    }
+
+    let text = String::from_utf8_lossy(prefix).to_lowercase();
+    let generated = text
+        .lines()
+        .take(MARKER_LINES)
+        .any(|line| GENERATED_MARKERS.iter().any(|m| line.contains(m)));
+    if generated {
+        return Some(FileClass::Generated);
+    }
+
+    None
+}
+
+#[derive(Clone)]
+struct AttributeLine {
+    pattern: String,
+    vendored: Option<bool>,
+    generated: Option<bool>,
+}
+
+fn parse_attribute_value(attr: &str, name: &str) -> Option<bool> {
+    if attr == name {
+        return Some(true);
+    }
+    if attr.strip_prefix('-') == Some(name) {
+        return Some(false);
+    }
+    match attr.strip_prefix(name)?.strip_prefix('=')? {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_gitattributes(text: &str) -> Vec<AttributeLine> {
+    let mut result = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let pattern = parts.next().unwrap().to_string();
+        let mut attr_line = AttributeLine {
+            pattern,
+            vendored: None,
+            generated: None,
+        };
+        for attr in parts {
+            if let Some(value) = parse_attribute_value(attr, "linguist-vendored") {
+                attr_line.vendored = Some(value);
+            }
+            if let Some(value) = parse_attribute_value(attr, "linguist-generated") {
+                attr_line.generated = Some(value);
+            }
+        }
+
+        if attr_line.vendored.is_some() || attr_line.generated.is_some() {
+            result.push(attr_line);
+        }
+    }
+    result
+}
+
+/// The linguist attributes of a single `.gitattributes` file.
+struct AttributeRules {
+    /// Directory containing the `.gitattributes` file, either empty or ending
+    /// in a `/`.
+    dir: String,
+    rules: Vec<(Gitignore, AttributeLine)>,
+}
+
+impl AttributeRules {
+    fn new(dir: String, lines: Vec<AttributeLine>) -> Self {
+        let rules = lines
+            .into_iter()
+            .filter_map(|line| {
+                let mut builder = GitignoreBuilder::new(PathBuf::new());
+                builder.add_line(None, &line.pattern).ok()?;
+                Some((builder.build().ok()?, line))
+            })
+            .collect();
+        Self { dir, rules }
+    }
+
+    /// Apply the rules to the attributes of a path. Later rules override
+    /// earlier ones.
+    fn apply(&self, path: &str, vendored: &mut Option<bool>, generated: &mut Option<bool>) {
+        let Some(relative) = path.strip_prefix(&self.dir) else {
+            return;
+        };
+        for (matcher, line) in &self.rules {
+            if matcher.matched(relative, false).is_ignore() {
+                *vendored = line.vendored.or(*vendored);
+                *generated = line.generated.or(*generated);
+            }
+        }
+    }
+}
+
+pub struct Linguist {
+    vendored_paths: Gitignore,
+    generated_paths: Gitignore,
+    content_by_blob: HashMap<Oid, Option<FileClass>>,
+    attributes_by_blob: HashMap<Oid, Vec<AttributeLine>>,
+}
+
+impl Linguist {
+    pub fn new() -> Self {
+        Self {
+            vendored_paths: build_gitignore(VENDORED_PATHS),
+            generated_paths: build_gitignore(GENERATED_PATHS),
+            content_by_blob: HashMap::new(),
+            attributes_by_blob: HashMap::new(),
+        }
+    }
+
+    fn load_attributes(
+        &mut self,
+        repo: &Path,
+        files: &HashMap<Arc<str>, Oid>,
+    ) -> anyhow::Result<Vec<AttributeRules>> {
+        let mut attribute_files = files
+            .iter()
+            .filter(|(path, _)| path.rsplit('/').next() == Some(".gitattributes"))
+            .collect::<Vec<_>>();
+
+        // Deeper files take precedence over shallower ones.
+        attribute_files.sort_unstable_by_key(|(path, _)| path.matches('/').count());
+
+        let missing = attribute_files
+            .iter()
+            .map(|(_, blob)| **blob)
+            .filter(|blob| !self.attributes_by_blob.contains_key(blob))
+            .collect::<Vec<_>>();
+        for (blob, content) in git::git_cat_blobs(repo, &missing, usize::MAX)? {
+            let lines = parse_gitattributes(&String::from_utf8_lossy(&content));
+            self.attributes_by_blob.insert(blob, lines);
+        }
+
+        let rules = attribute_files
+            .into_iter()
+            .map(|(path, blob)| {
+                let dir = path.strip_suffix(".gitattributes").unwrap().to_string();
+                let lines = self
+                    .attributes_by_blob
+                    .get(blob)
+                    .cloned()
+                    .unwrap_or_default();
+                AttributeRules::new(dir, lines)
+            })
+            .collect();
+        Ok(rules)
+    }
+
+    /// Classify all files of a commit, given as a map from path to blob.
+    /// Regular files are omitted from the result.
+    pub fn classify(
+        &mut self,
+        repo: &Path,
+        files: &HashMap<Arc<str>, Oid>,
+    ) -> anyhow::Result<HashMap<Arc<str>, FileClass>> {
+        let attributes = self.load_attributes(repo, files)?;
+
+        let missing = files
+            .values()
+            .filter(|blob| !self.content_by_blob.contains_key(*blob))
+            .copied()
+            .collect::<Vec<_>>();
+        for (blob, prefix) in git::git_cat_blobs(repo, &missing, BINARY_PREFIX_LEN)? {
+            self.content_by_blob.insert(blob, classify_content(&prefix));
+        }
+
+        let mut classes = HashMap::new();
+        for (path, blob) in files {
+            let mut vendored = None;
+            let mut generated = None;
+            for rules in &attributes {
+                rules.apply(path, &mut vendored, &mut generated);
+            }
+
+            let vendored = vendored.unwrap_or_else(|| {
+                self.vendored_paths
+                    .matched_path_or_any_parents(&**path, false)
+                    .is_ignore()
+            });
+            if vendored {
+                classes.insert(path.clone(), FileClass::Vendored);
+                continue;
+            }
+
+            let content = self.content_by_blob.get(blob).copied().flatten();
+            let generated = generated.unwrap_or_else(|| {
+                content == Some(FileClass::Generated)
+                    || self
+                        .generated_paths
+                        .matched_path_or_any_parents(&**path, false)
+                        .is_ignore()
+            });
+            if generated {
+                classes.insert(path.clone(), FileClass::Generated);
+                continue;
+            }
+
+            if content == Some(FileClass::Binary) {
+                classes.insert(path.clone(), FileClass::Binary);
+            }
+        }
+        Ok(classes)
+    }
+}
@@ -1,80 +1,227 @@
+mod bucket;
 mod common;
+mod filter;
 #[allow(clippy::module_inception)]
 mod graph;
+mod identity;
+mod index;
 mod series;
+mod total;
 
 use std::{
     collections::{HashMap, HashSet},
+    hash::Hash,
     path::Path,
+    sync::Arc,
 };
 
+use bucket::{BucketKey, Bucketing};
+use common::MissingData;
+use filter::PathFilter;
 use graph::Graph;
-use ignore::gitignore::Gitignore;
-use jiff::tz::TimeZone;
-use lru::LruCache;
+use identity::Identities;
+pub use index::current_files_parts;
+use index::Index;
+use rayon::{iter::ParallelIterator, slice::ParallelSlice};
+use serde::{de::DeserializeOwned, Serialize};
 use series::Series;
+use total::RunningTotal;
 use unicode_width::UnicodeWidthStr;
 
 use crate::{
-    data::{Authors, BlameId, BlameTree, Data},
-    progress, OutFormat,
+    data::{BlameId, BlameTree, Data, HeapSize, Oid, ShardedCache},
+    progress, AuthorArgs, BotMode, BucketKind, FilterArgs, GlobalArgs, GraphArgs, OutFormat,
+    PathArgs,
 };
 
+//////////////
+// Counting //
+//////////////
+
+/// Counts of individual blames, shared between the threads counting commits.
+type CountCache<K> = ShardedCache<BlameId, Arc<HashMap<K, u64>>>;
+
+/// Commits counted one after another by the same thread. All but the first
+/// are counted from the previous commit's total.
+const COMMITS_PER_TASK: usize = 64;
+
+/// Count the lines of a commit, starting from the total of the commit counted
+/// before it. Counts of commits and blames are taken from the index or the
+/// cache if possible, and saved to the index otherwise.
+fn count_commit<K, F>(
+    data: &Data,
+    cache: &CountCache<K>,
+    index: &Index,
+    filter: &PathFilter,
+    total: &mut RunningTotal<K>,
+    hash: Oid,
+    count_blame: F,
+) -> anyhow::Result<HashMap<K, u64>>
+where
+    K: Clone + Eq + Hash + HeapSize + Serialize + DeserializeOwned,
+    F: Fn(&BlameId) -> anyhow::Result<HashMap<K, u64>>,
+{
+    if let Some(count) = index.load_commit(data, hash)? {
+        return Ok(count);
+    }
+
+    let load_count = |blame_id: &BlameId| {
+        if let Some(count) = cache.get(blame_id) {
+            return Ok(count);
+        }
+
+        let count = Arc::new(count_blame(blame_id)?);
+        cache.put(blame_id.clone(), count.clone());
+        Ok(count)
+    };
+
+    // If the blametree is stored as a delta to the commit counted before,
+    // only the blames in the delta need to be looked at. Errors loading the
+    // delta are reported when loading the full blametree instead.
+    let delta = match total.commit() {
+        Some(prev) => (data.load_blametree_delta(hash).ok().flatten())
+            .filter(|delta| delta.parent == prev && delta.classes.is_none()),
+        None => None,
+    };
+
+    let result = if let Some(delta) = delta {
+        let changed = delta
+            .changed
+            .into_iter()
+            .filter(|blame_id| {
+                let class = total.classes().get(&blame_id.path).copied();
+                !filter.is_excluded(&blame_id.path, class)
+            })
+            .collect();
+        total.apply_delta(hash, delta.removed, changed, load_count)
+    } else {
+        let blametree = match data.load_blametree_cached(hash) {
+            Ok(blametree) => blametree,
+            Err(e) => {
+                let mut missing = MissingData::default();
+                missing.add(None, e);
+                return Err(missing.into());
+            }
+        };
+        let blames = blametree
+            .blames
+            .into_iter()
+            .filter(|blame_id| {
+                let class = blametree.classes.get(&blame_id.path).copied();
+                !filter.is_excluded(&blame_id.path, class)
+            })
+            .collect::<HashSet<_>>();
+        total.update(hash, blametree.classes, blames, load_count)
+    };
+
+    result.map_err(|errors| {
+        let mut missing = MissingData::default();
+        for (blame_id, e) in errors {
+            missing.add(Some(&blame_id.path), e);
+        }
+        missing
+    })?;
+
+    let count = total.counts();
+    index.save_commit(data, hash, &count)?;
+    Ok(count)
+}
+
+/// Count the lines of many commits in parallel, in the same order. Runs of
+/// consecutive commits are counted by the same thread, so neighbouring
+/// commits should be similar. Each run is counted from its end, since the
+/// commits are usually newest first and blametrees are deltas to their parent.
+fn count_commits<K, F>(name: &str, hashes: &[Oid], count: F) -> Vec<anyhow::Result<HashMap<K, u64>>>
+where
+    K: Send,
+    F: Fn(&mut RunningTotal<K>, Oid) -> anyhow::Result<HashMap<K, u64>> + Sync,
+{
+    let pb = progress::counting_bar(name, hashes.len());
+    let counts = hashes
+        .par_chunks(COMMITS_PER_TASK)
+        .flat_map_iter(|chunk| {
+            let mut total = RunningTotal::default();
+            let mut counts = chunk
+                .iter()
+                .rev()
+                .map(|&hash| {
+                    let count = count(&mut total, hash);
+                    pb.inc(1);
+                    count
+                })
+                .collect::<Vec<_>>();
+            counts.reverse();
+            counts
+        })
+        .collect();
+    pb.finish();
+    counts
+}
+
 ///////////////
 // By author //
 ///////////////
 
-fn count_authors(
-    data: &mut Data,
-    count_cache: &mut LruCache<BlameId, HashMap<String, u64>>,
-    ignore: &Gitignore,
-    authors: &Authors,
-    blametree: BlameTree,
-    use_email: bool,
-) -> anyhow::Result<HashMap<String, u64>> {
-    let mut count = HashMap::<String, u64>::new();
-    for blame_id in blametree.blames {
-        if ignore
-            .matched_path_or_any_parents(&blame_id.path, false)
-            .is_ignore()
-        {
-            continue;
-        }
+fn authors_index(
+    data: &Data,
+    author_args: &AuthorArgs,
+    identities: &Identities,
+    filter: &PathFilter,
+) -> anyhow::Result<Index> {
+    let counting = format!("authors {}", identities.describe());
+    Index::load(
+        data,
+        author_args.mailmap.as_deref(),
+        &counting,
+        filter.describe(),
+    )
+}
 
-        if let Some(cached_count) = count_cache.get(&blame_id) {
-            for (author, amount) in cached_count {
-                *count.entry(author.clone()).or_default() += amount;
-            }
+fn count_blame_authors(
+    data: &Data,
+    identities: &Identities,
+    blame_id: &BlameId,
+) -> anyhow::Result<HashMap<String, u64>> {
+    let mut count = HashMap::new();
+    let blame = data.load_blame_cached(blame_id)?;
+    for (hash, amount) in blame.lines_by_commit {
+        let info = data.load_commit_cached(hash)?;
+        if identities.is_excluded(&info) {
             continue;
         }
-
-        let mut cached_count = HashMap::new();
-        let blame = data.load_blame_cached(&blame_id)?;
-        for (hash, amount) in blame.lines_by_commit {
-            let info = data.load_commit_cached(hash.clone())?;
-            let author = if use_email {
-                &info.author_mail
-            } else {
-                &info.author
-            };
-            let author = authors.get(author);
-            *count.entry(author.clone()).or_default() += amount;
-            *cached_count.entry(author).or_default() += amount;
+        for (author, amount) in identities.credit(&info, amount) {
+            *count.entry(author).or_default() += amount;
         }
-        count_cache.put(blame_id, cached_count);
     }
     Ok(count)
 }
 
-pub fn print_authors(data: &mut Data, hash: Option<String>, use_email: bool) -> anyhow::Result<()> {
+pub fn print_authors(
+    data: &mut Data,
+    global: &GlobalArgs,
+    hash: Option<Oid>,
+    filter: &FilterArgs,
+    author_args: &AuthorArgs,
+) -> anyhow::Result<()> {
     let log = data.load_log_uncached()?;
     let hash = common::first_hash(&log, hash)?;
-    let blametree = data.load_blametree_cached(hash)?;
-    let ignore = data.load_ignore_uncached()?;
-    let authors = data.load_authors_uncached()?;
-
-    let mut cache = LruCache::new(10000.try_into().unwrap());
-    let count = count_authors(data, &mut cache, &ignore, &authors, blametree, use_email)?;
+    let identities = Identities::load(data, global, author_args, filter.bots)?;
+    let filter = PathFilter::load(data, &filter.paths)?;
+    let index = authors_index(data, author_args, &identities, &filter)?.without_saving();
+
+    let cache = ShardedCache::new(data.command_cache_memory());
+    let mut total = RunningTotal::default();
+    let count = count_commit(
+        data,
+        &cache,
+        &index,
+        &filter,
+        &mut total,
+        hash,
+        |blame_id| count_blame_authors(data, &identities, blame_id),
+    )?;
+    data.record_cache_stats("author counts", cache.stats());
+    let count = identity::round_credits(count);
     let mut count = count.into_iter().map(|(a, n)| (n, a)).collect::<Vec<_>>();
     count.sort_unstable();
 
@@ -89,33 +236,42 @@ pub fn print_authors(data: &mut Data, hash: Option<String>, use_email: bool) ->
 
 pub fn graph_authors(
     data: &mut Data,
+    global: &GlobalArgs,
     outfile: &Path,
-    format: OutFormat,
-    use_email: bool,
+    graph_args: &GraphArgs,
+    filter: &FilterArgs,
+    author_args: &AuthorArgs,
 ) -> anyhow::Result<()> {
     println!("Loading basic info");
     let log = data.load_log_uncached()?;
-    let ignore = data.load_ignore_uncached()?;
-    let authors = data.load_authors_uncached()?;
-    let tz = TimeZone::system();
-
-    let mut commits = common::load_commits(data, log)?;
-    common::order_for_equidistance(&tz, &mut commits);
-
-    let pb = progress::counting_bar("Loading blames", commits.len());
-    let mut cache = LruCache::new(10000.try_into().unwrap());
-    let mut counts = vec![];
-    for commit in commits {
-        let blametree = data.load_blametree_cached(commit.hash.clone())?;
-        let Ok(count) = count_authors(data, &mut cache, &ignore, &authors, blametree, use_email)
-        else {
-            break;
-        };
-        counts.push((commit, count));
-        pb.inc(1);
+    let identities = Identities::load(data, global, author_args, filter.bots)?;
+    let filter = PathFilter::load(data, &filter.paths)?;
+    let index = authors_index(data, author_args, &identities, &filter)?;
+    let spacing = Bucketing::load(data, global, graph_args.spacing)?;
+    let tags = data.load_tags_uncached()?;
+    let annotations = data.load_annotations_uncached()?.annotations;
+    let tz = common::load_tz(data, global)?;
+
+    let (mut commits, mut unloaded) = common::try_load_commits(data, log);
+    if graph_args.releases_only {
+        common::retain_tagged(&tags, &mut commits, &mut unloaded)?;
     }
-    pb.set_length(pb.position());
-    pb.finish();
+    common::order_for_equidistance(&spacing, &mut commits);
+
+    let hashes = commits.iter().map(|c| c.hash).collect::<Vec<_>>();
+    let cache = ShardedCache::new(data.command_cache_memory());
+    let counts = count_commits("Loading blames", &hashes, |total, hash| {
+        count_commit(data, &cache, &index, &filter, total, hash, |blame_id| {
+            count_blame_authors(data, &identities, blame_id)
+        })
+    });
+    data.record_cache_stats("author counts", cache.stats());
+    data.flush()?;
+    let counts = counts
+        .into_iter()
+        .map(|count| count.map(identity::round_credits));
+    let counts = commits.into_iter().zip(counts).collect();
+    let (counts, gaps) = common::fill_gaps(graph_args.missing, counts, unloaded)?;
 
     println!("Crunching numbers");
 
@@ -155,7 +311,8 @@ pub fn graph_authors(
         let mut misc_authors = series.pop().unwrap();
         let n_misc_authors = series.len() - real_authors;
         misc_authors.name = format!(
-            "{n_misc_authors} misc. author{}",
+            "{n_misc_authors} misc. {}{}",
+            identities.dimension(),
             if n_misc_authors == 1 { "" } else { "s" }
         );
         while series.len() > real_authors {
@@ -165,138 +322,453 @@ pub fn graph_authors(
     }
 
     println!("Saving data");
-    let mut graph = Graph::new("Lines per author", commits, time, series);
-    graph.make_equidistant(tz);
-    match format {
+    let title = format!("Lines per {}", identities.dimension());
+    let mut graph = Graph::new(&title, &tz, commits, time, series);
+    graph.make_equidistant(&spacing);
+    graph.add_tags(tags);
+    graph.add_annotations(annotations, &tz);
+    graph.add_gaps(gaps);
+    match graph_args.format {
         OutFormat::Html => graph.save_html(outfile)?,
         OutFormat::Json => graph.save_json(outfile)?,
     }
     Ok(())
 }
 
+//////////////
+// Excluded //
+//////////////
+
+pub fn print_excluded(data: &mut Data, hash: Option<Oid>, paths: &PathArgs) -> anyhow::Result<()> {
+    let log = data.load_log_uncached()?;
+    let hash = common::first_hash(&log, hash)?;
+    let blametree = data.load_blametree_cached(hash)?;
+    let filter = PathFilter::load(data, paths)?;
+
+    let mut excluded = blametree
+        .blames
+        .iter()
+        .filter_map(|blame_id| {
+            let class = blametree.classes.get(&blame_id.path).copied();
+            let exclusion = filter.exclusion(&blame_id.path, class)?;
+            Some((&blame_id.path, exclusion.to_string()))
+        })
+        .collect::<Vec<_>>();
+    excluded.sort_unstable();
+
+    let width = excluded.iter().map(|(_, e)| e.width()).max().unwrap_or(0);
+    for (path, exclusion) in excluded {
+        println!("{exclusion:width$} {path}");
+    }
+
+    Ok(())
+}
+
 /////////////
 // By year //
 /////////////
 
-fn count_years(
-    data: &mut Data,
-    count_cache: &mut LruCache<BlameId, HashMap<i16, u64>>,
-    ignore: &Gitignore,
-    tz: &TimeZone,
-    blametree: BlameTree,
-) -> anyhow::Result<HashMap<i16, u64>> {
-    let mut count = HashMap::<i16, u64>::new();
-    for blame_id in blametree.blames {
-        if ignore
-            .matched_path_or_any_parents(&blame_id.path, false)
-            .is_ignore()
-        {
-            continue;
-        }
+fn years_index(
+    data: &Data,
+    identities: &Identities,
+    age: &Bucketing,
+    filter: &PathFilter,
+) -> anyhow::Result<Index> {
+    let counting = format!("years {} {}", identities.describe(), age.describe());
+    Index::load(data, None, &counting, filter.describe())
+}
 
-        if let Some(cached_count) = count_cache.get(&blame_id) {
-            for (year, amount) in cached_count {
-                *count.entry(*year).or_default() += amount;
-            }
+fn count_blame_years(
+    data: &Data,
+    identities: &Identities,
+    age: &Bucketing,
+    blame_id: &BlameId,
+) -> anyhow::Result<HashMap<BucketKey, u64>> {
+    let mut count = HashMap::new();
+    let blame = data.load_blame_cached(blame_id)?;
+    for (hash, amount) in blame.lines_by_commit {
+        let info = data.load_commit_cached(hash)?;
+        if identities.is_excluded(&info) {
             continue;
         }
-
-        let mut cached_count = HashMap::new();
-        let blame = data.load_blame_cached(&blame_id)?;
-        for (hash, amount) in blame.lines_by_commit {
-            let info = data.load_commit_cached(hash.clone())?;
-            let year = tz.to_datetime(info.author_time).year();
-            *count.entry(year).or_default() += amount;
-            *cached_count.entry(year).or_default() += amount;
-        }
-        count_cache.put(blame_id, cached_count);
+        let key = age.key(identities.date(&info));
+        *count.entry(key).or_default() += amount;
     }
     Ok(count)
 }
 
-pub fn print_years(data: &mut Data, hash: Option<String>) -> anyhow::Result<()> {
+pub fn print_years(
+    data: &mut Data,
+    global: &GlobalArgs,
+    hash: Option<Oid>,
+    filter: &FilterArgs,
+    age: BucketKind,
+) -> anyhow::Result<()> {
     let log = data.load_log_uncached()?;
     let hash = common::first_hash(&log, hash)?;
-    let blametree = data.load_blametree_cached(hash)?;
-    let ignore = data.load_ignore_uncached()?;
-    let tz = TimeZone::system();
-
-    let mut cache = LruCache::new(10000.try_into().unwrap());
-    let count = count_years(data, &mut cache, &ignore, &tz, blametree)?;
+    let identities = Identities::load(data, global, &AuthorArgs::default(), filter.bots)?;
+    let filter = PathFilter::load(data, &filter.paths)?;
+    let age = Bucketing::load(data, global, age)?;
+    let index = years_index(data, &identities, &age, &filter)?.without_saving();
+
+    let cache = ShardedCache::new(data.command_cache_memory());
+    let mut total = RunningTotal::default();
+    let count = count_commit(
+        data,
+        &cache,
+        &index,
+        &filter,
+        &mut total,
+        hash,
+        |blame_id| count_blame_years(data, &identities, &age, blame_id),
+    )?;
+    data.record_cache_stats("age counts", cache.stats());
     let mut count = count.into_iter().collect::<Vec<_>>();
     count.sort_unstable();
 
-    for (y, n) in count {
-        let n = format!("{n}");
-        let y = format!("{y:4}");
-        let space = (18 - y.width() - n.width()).max(1);
-        println!("{y} {} {n}", ".".repeat(space));
+    let count = count
+        .into_iter()
+        .map(|(key, n)| (age.label(key), format!("{n}")))
+        .collect::<Vec<_>>();
+    let label_width = count.iter().map(|(l, _)| l.width()).max().unwrap_or(0);
+
+    for (l, n) in count {
+        let l = format!("{l:label_width$}");
+        let space = (14 + label_width - l.width() - n.width()).max(1);
+        println!("{l} {} {n}", ".".repeat(space));
     }
 
     Ok(())
 }
 
-pub fn graph_years(data: &mut Data, outfile: &Path, format: OutFormat) -> anyhow::Result<()> {
+pub fn graph_years(
+    data: &mut Data,
+    global: &GlobalArgs,
+    outfile: &Path,
+    graph_args: &GraphArgs,
+    filter: &FilterArgs,
+    age: BucketKind,
+) -> anyhow::Result<()> {
     println!("Loading basic info");
     let log = data.load_log_uncached()?;
-    let ignore = data.load_ignore_uncached()?;
-    let tz = TimeZone::system();
-
-    let mut commits = common::load_commits(data, log)?;
-    common::order_for_equidistance(&tz, &mut commits);
-
-    let pb = progress::counting_bar("Loading blames", commits.len());
-    let mut cache = LruCache::new(10000.try_into().unwrap());
-    let mut counts = vec![];
-    for commit in commits {
-        let blametree = data.load_blametree_cached(commit.hash.clone())?;
-        let Ok(count) = count_years(data, &mut cache, &ignore, &tz, blametree) else {
-            break;
-        };
-        counts.push((commit, count));
-        pb.inc(1);
+    let identities = Identities::load(data, global, &AuthorArgs::default(), filter.bots)?;
+    let filter = PathFilter::load(data, &filter.paths)?;
+    let age = Bucketing::load(data, global, age)?;
+    let index = years_index(data, &identities, &age, &filter)?;
+    let spacing = Bucketing::load(data, global, graph_args.spacing)?;
+    let tags = data.load_tags_uncached()?;
+    let annotations = data.load_annotations_uncached()?.annotations;
+    let tz = common::load_tz(data, global)?;
+
+    let (mut commits, mut unloaded) = common::try_load_commits(data, log);
+    if graph_args.releases_only {
+        common::retain_tagged(&tags, &mut commits, &mut unloaded)?;
     }
-    pb.set_length(pb.position());
-    pb.finish();
+    common::order_for_equidistance(&spacing, &mut commits);
+
+    let hashes = commits.iter().map(|c| c.hash).collect::<Vec<_>>();
+    let cache = ShardedCache::new(data.command_cache_memory());
+    let counts = count_commits("Loading blames", &hashes, |total, hash| {
+        count_commit(data, &cache, &index, &filter, total, hash, |blame_id| {
+            count_blame_years(data, &identities, &age, blame_id)
+        })
+    });
+    data.record_cache_stats("age counts", cache.stats());
+    data.flush()?;
+    let counts = commits.into_iter().zip(counts).collect();
+    let (counts, gaps) = common::fill_gaps(graph_args.missing, counts, unloaded)?;
 
     println!("Crunching numbers");
 
-    let all_years = counts
+    let all_keys = counts
         .iter()
         .flat_map(|(_, count)| count.keys().copied())
         .collect::<HashSet<_>>();
 
-    let min_year = *all_years.iter().min().unwrap();
-    let max_year = *all_years.iter().max().unwrap();
+    // Without any lines, like when no file is included, the graph is empty
+    // just like the authors graph.
+    let keys = match (all_keys.iter().min(), all_keys.iter().max()) {
+        (Some(min_key), Some(max_key)) => age.range(*min_key, *max_key),
+        _ => vec![],
+    };
 
     let mut commits = vec![];
     let mut time = vec![];
-    let mut by_year = (min_year..=max_year)
-        .map(|year| (year, Series::new(year)))
+    let mut by_key = keys
+        .iter()
+        .map(|key| (*key, Series::new(age.label(*key))))
         .collect::<HashMap<_, _>>();
 
     for (commit, count) in counts {
-        for year in min_year..=max_year {
-            let amount = count.get(&year).copied().unwrap_or(0);
-            by_year.get_mut(&year).unwrap().push(amount);
+        for key in &keys {
+            let amount = count.get(key).copied().unwrap_or(0);
+            by_key.get_mut(key).unwrap().push(amount);
         }
         time.push(commit.committer_time.as_second());
         commits.push(commit)
     }
 
-    let mut series = by_year.into_iter().collect::<Vec<_>>();
-    series.sort_unstable_by_key(|(year, _)| *year);
+    let mut series = by_key.into_iter().collect::<Vec<_>>();
+    series.sort_unstable_by_key(|(key, _)| *key);
     let series = series
         .into_iter()
         .map(|(_, series)| series)
         .collect::<Vec<_>>();
 
     println!("Saving data");
-    let mut graph = Graph::new("Lines per year", commits, time, series);
-    graph.make_equidistant(tz);
-    match format {
+    let title = format!("Lines per {}", age.name());
+    let mut graph = Graph::new(&title, &tz, commits, time, series);
+    graph.make_equidistant(&spacing);
+    graph.add_tags(tags);
+    graph.add_annotations(annotations, &tz);
+    graph.add_gaps(gaps);
+    match graph_args.format {
         OutFormat::Html => graph.save_html(outfile)?,
         OutFormat::Json => graph.save_json(outfile)?,
     }
     Ok(())
 }
+
+//////////////
+// Activity //
+//////////////
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+pub fn print_activity(data: &mut Data, global: &GlobalArgs, bots: BotMode) -> anyhow::Result<()> {
+    let log = data.load_log_uncached()?;
+    let identities = Identities::load(data, global, &AuthorArgs::default(), bots)?;
+    let commits = common::load_commits(data, log)?;
+
+    let mut count = [[0_u64; 24]; 7];
+    for commit in commits {
+        if identities.is_excluded(&commit) {
+            continue;
+        }
+        let time = commit.author_local_time();
+        let weekday = time.weekday().to_monday_zero_offset() as usize;
+        count[weekday][time.hour() as usize] += 1;
+    }
+
+    let rows = WEEKDAYS
+        .iter()
+        .zip(count)
+        .map(|(day, hours)| (day.to_string(), hours))
+        .collect::<Vec<_>>();
+    common::print_hour_heatmap(&rows, false);
+
+    Ok(())
+}
+
+///////////////////
+// Working hours //
+///////////////////
+
+fn count_hours(
+    data: &Data,
+    filter: &PathFilter,
+    identities: &Identities,
+    blametree: BlameTree,
+) -> anyhow::Result<HashMap<String, [u64; 24]>> {
+    let mut count = HashMap::<String, [u64; 24]>::new();
+    for blame_id in blametree.blames {
+        let class = blametree.classes.get(&blame_id.path).copied();
+        if filter.is_excluded(&blame_id.path, class) {
+            continue;
+        }
+
+        let blame = data.load_blame_cached(&blame_id)?;
+        for (hash, amount) in blame.lines_by_commit {
+            let info = data.load_commit_cached(hash)?;
+            if identities.is_excluded(&info) {
+                continue;
+            }
+            let hour = info.author_local_time().hour() as usize;
+            for (author, amount) in identities.credit(&info, amount) {
+                count.entry(author).or_insert([0; 24])[hour] += amount;
+            }
+        }
+    }
+    for hours in count.values_mut() {
+        *hours = hours.map(identity::round_credit);
+    }
+    Ok(count)
+}
+
+pub fn print_working_hours(
+    data: &mut Data,
+    global: &GlobalArgs,
+    hash: Option<Oid>,
+    filter: &FilterArgs,
+    author_args: &AuthorArgs,
+) -> anyhow::Result<()> {
+    let log = data.load_log_uncached()?;
+    let hash = common::first_hash(&log, hash)?;
+    let blametree = data.load_blametree_cached(hash)?;
+    let identities = Identities::load(data, global, author_args, filter.bots)?;
+    let filter = PathFilter::load(data, &filter.paths)?;
+
+    let count = count_hours(data, &filter, &identities, blametree)?;
+    let mut rows = count.into_iter().collect::<Vec<_>>();
+    rows.sort_unstable_by_key(|(a, hours)| (hours.iter().sum::<u64>(), a.clone()));
+    common::print_hour_heatmap(&rows, true);
+
+    Ok(())
+}
+
+///////////
+// Index //
+///////////
+
+pub fn index(
+    data: &mut Data,
+    global: &GlobalArgs,
+    filter_args: &FilterArgs,
+    author_args: &AuthorArgs,
+    age: BucketKind,
+) -> anyhow::Result<()> {
+    let log = data.load_log_uncached()?;
+    let filter = PathFilter::load(data, &filter_args.paths)?;
+
+    let identities = Identities::load(data, global, author_args, filter_args.bots)?;
+    let index = authors_index(data, author_args, &identities, &filter)?;
+    let cache = ShardedCache::new(data.command_cache_memory());
+    let authors = count_commits("Indexing authors", &log, |total, hash| {
+        count_commit(data, &cache, &index, &filter, total, hash, |blame_id| {
+            count_blame_authors(data, &identities, blame_id)
+        })
+    });
+    data.record_cache_stats("author counts", cache.stats());
+    drop(cache);
+
+    let identities = Identities::load(data, global, &AuthorArgs::default(), filter_args.bots)?;
+    let age = Bucketing::load(data, global, age)?;
+    let index = years_index(data, &identities, &age, &filter)?;
+    let cache = ShardedCache::new(data.command_cache_memory());
+    let years = count_commits("Indexing years", &log, |total, hash| {
+        count_commit(data, &cache, &index, &filter, total, hash, |blame_id| {
+            count_blame_years(data, &identities, &age, blame_id)
+        })
+    });
+    data.record_cache_stats("age counts", cache.stats());
+
+    data.flush()?;
+    let missing = (authors.iter().zip(&years))
+        .filter(|(a, y)| a.is_err() || y.is_err())
+        .count();
+    if missing > 0 {
+        println!("Skipped {missing} commits with missing data, run fsck for details");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::Timestamp;
+
+    use crate::{
+        data::{Blame, Commit, Kind},
+        MissingMode,
+    };
+
+    use super::*;
+
+    fn oid(n: u8) -> Oid {
+        format!("{n:040x}").parse().unwrap()
+    }
+
+    fn commit(hash: Oid) -> Commit {
+        Commit {
+            hash,
+            parents: vec![],
+            author: "A U Thor".to_string(),
+            author_mail: "author@example.com".to_string(),
+            author_time: Timestamp::UNIX_EPOCH,
+            author_offset: 0,
+            committer: "A U Thor".to_string(),
+            committer_mail: "author@example.com".to_string(),
+            committer_time: Timestamp::UNIX_EPOCH,
+            subject: "Commit".to_string(),
+            trailers: vec![],
+        }
+    }
+
+    /// Save a commit with a single file whose lines were written by `authors`.
+    fn save_commit(data: &mut Data, hash: Oid, authors: &[Oid]) {
+        let id = BlameId {
+            commit: hash,
+            blob: oid(0xff),
+            path: "file.txt".into(),
+        };
+        let lines_by_commit = authors.iter().map(|&author| (author, 1)).collect();
+        data.save_commit(&commit(hash)).unwrap();
+        data.save_blame(&Blame {
+            id: id.clone(),
+            lines_by_commit,
+        })
+        .unwrap();
+        let blametree = BlameTree {
+            commit: hash,
+            blames: vec![id],
+            classes: HashMap::new(),
+        };
+        data.save_blametree(&blametree, None).unwrap();
+    }
+
+    #[test]
+    fn missing_commit_is_reported_as_gap() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = Data::new(dir.path().to_path_buf(), None, true).unwrap();
+        save_commit(&mut data, oid(1), &[oid(1)]);
+        save_commit(&mut data, oid(3), &[oid(1), oid(2)]);
+        save_commit(&mut data, oid(2), &[oid(2)]);
+        let store = data.store();
+        store.remove(Kind::Commit, &oid(2).to_string()).unwrap();
+        store.flush().unwrap();
+
+        let paths = PathArgs {
+            include: vec![],
+            exclude: vec![],
+            linguist: false,
+        };
+        let filter = PathFilter::load(&data, &paths).unwrap();
+        let index = Index::load(&data, None, "test", filter.describe())
+            .unwrap()
+            .without_saving();
+        let cache = ShardedCache::new(1 << 20);
+        let count_blame = |blame_id: &BlameId| {
+            let mut count = HashMap::new();
+            let blame = data.load_blame_cached(blame_id)?;
+            for (hash, amount) in blame.lines_by_commit {
+                let info = data.load_commit_cached(hash)?;
+                *count.entry(info.author).or_default() += amount;
+            }
+            Ok(count)
+        };
+        let counts = [oid(1), oid(3)]
+            .into_iter()
+            .map(|hash| {
+                let mut total = RunningTotal::default();
+                let count = count_commit(
+                    &data,
+                    &cache,
+                    &index,
+                    &filter,
+                    &mut total,
+                    hash,
+                    count_blame,
+                );
+                (commit(hash), count)
+            })
+            .collect();
+
+        let (counts, gaps) = common::fill_gaps(MissingMode::Skip, counts, vec![]).unwrap();
+        assert_eq!(counts.len(), 1);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].commit, oid(3));
+        let object = format!("commit {} for file.txt", oid(2));
+        assert_eq!(gaps[0].reason, format!("{object} not found"));
+        assert_eq!(gaps[0].not_found, [object]);
+        assert!(gaps[0].corrupt.is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+use jiff::{
+    civil::{Date, DateTime},
+    tz::TimeZone,
+    Timestamp, ToSpan,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::{Data, HeapSize},
+    BucketKind, GlobalArgs,
+};
+
+use super::common;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum BucketKey {
+    /// First day of a calendar-based bucket.
+    Date(Date),
+    /// Index of the release a commit first appeared in. One past the last
+    /// release means the commit is not released yet.
+    Release(usize),
+}
+
+impl HeapSize for BucketKey {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+/// Splits time into consecutive buckets like years, months or releases.
+pub struct Bucketing {
+    kind: BucketKind,
+    fiscal_start: i8,
+    tz: TimeZone,
+    /// Release names and times, in chronological order.
+    releases: Vec<(Timestamp, String)>,
+}
+
+impl Bucketing {
+    pub fn load(data: &mut Data, global: &GlobalArgs, kind: BucketKind) -> anyhow::Result<Self> {
+        let mut releases = vec![];
+        if kind == BucketKind::Release {
+            for tag in data.load_tags_uncached()? {
+                let commit = data.load_commit_cached(tag.commit)?;
+                releases.push((commit.committer_time, tag.name));
+            }
+            if releases.is_empty() {
+                anyhow::bail!("found no tags, try gathering again");
+            }
+            releases.sort_unstable();
+        }
+
+        Ok(Self {
+            kind,
+            fiscal_start: global.fiscal_start,
+            tz: common::load_tz(data, global)?,
+            releases,
+        })
+    }
+
+    /// Everything the buckets depend on, for telling apart counts computed
+    /// with different buckets.
+    pub fn describe(&self) -> String {
+        format!(
+            "{:?} {} {} {:?}",
+            self.kind,
+            self.fiscal_start,
+            common::describe_tz(&self.tz),
+            self.releases,
+        )
+    }
+
+    /// Name of a single bucket, for titles.
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            BucketKind::Year => "year",
+            BucketKind::FiscalYear => "fiscal year",
+            BucketKind::Quarter => "quarter",
+            BucketKind::Month => "month",
+            BucketKind::Week => "week",
+            BucketKind::Release => "release",
+        }
+    }
+
+    fn start_date(&self, date: Date) -> Date {
+        let (year, month) = (date.year(), date.month());
+        match self.kind {
+            BucketKind::Year => Date::new(year, 1, 1).unwrap(),
+            BucketKind::FiscalYear if month >= self.fiscal_start => {
+                Date::new(year, self.fiscal_start, 1).unwrap()
+            }
+            BucketKind::FiscalYear => Date::new(year - 1, self.fiscal_start, 1).unwrap(),
+            BucketKind::Quarter => Date::new(year, (month - 1) / 3 * 3 + 1, 1).unwrap(),
+            BucketKind::Month => Date::new(year, month, 1).unwrap(),
+            BucketKind::Week => {
+                let offset = date.weekday().to_monday_zero_offset();
+                date.checked_sub(i64::from(offset).days()).unwrap()
+            }
+            BucketKind::Release => unreachable!(),
+        }
+    }
+
+    pub fn key(&self, ts: Timestamp) -> BucketKey {
+        if self.kind == BucketKind::Release {
+            return BucketKey::Release(self.releases.partition_point(|(t, _)| *t < ts));
+        }
+        BucketKey::Date(self.start_date(self.tz.to_datetime(ts).date()))
+    }
+
+    fn next(&self, key: BucketKey) -> BucketKey {
+        match key {
+            BucketKey::Release(i) => BucketKey::Release(i + 1),
+            BucketKey::Date(date) => BucketKey::Date(
+                match self.kind {
+                    BucketKind::Year | BucketKind::FiscalYear => date.checked_add(1.year()),
+                    BucketKind::Quarter => date.checked_add(3.months()),
+                    BucketKind::Month => date.checked_add(1.month()),
+                    BucketKind::Week => date.checked_add(1.week()),
+                    BucketKind::Release => unreachable!(),
+                }
+                .unwrap(),
+            ),
+        }
+    }
+
+    /// All keys from `min` to `max`, inclusive.
+    pub fn range(&self, min: BucketKey, max: BucketKey) -> Vec<BucketKey> {
+        let mut keys = vec![];
+        let mut key = min;
+        while key <= max {
+            keys.push(key);
+            key = self.next(key);
+        }
+        keys
+    }
+
+    pub fn label(&self, key: BucketKey) -> String {
+        let date = match key {
+            BucketKey::Release(i) => {
+                return match self.releases.get(i) {
+                    Some((_, name)) => name.clone(),
+                    None => "unreleased".to_string(),
+                }
+            }
+            BucketKey::Date(date) => date,
+        };
+
+        match self.kind {
+            BucketKind::Year => format!("{}", date.year()),
+            BucketKind::FiscalYear => {
+                // Fiscal years are named after the year they end in.
+                let year = date.year() + i16::from(self.fiscal_start > 1);
+                format!("FY{year}")
+            }
+            BucketKind::Quarter => format!("{}-Q{}", date.year(), (date.month() - 1) / 3 + 1),
+            BucketKind::Month => format!("{}-{:02}", date.year(), date.month()),
+            BucketKind::Week => {
+                // The ISO week belongs to the year its thursday is in.
+                let thursday = date.checked_add(3.days()).unwrap();
+                let week = (thursday.day_of_year() - 1) / 7 + 1;
+                format!("{}-W{week:02}", thursday.year())
+            }
+            BucketKind::Release => unreachable!(),
+        }
+    }
+
+    /// Start and end of a bucket, if known. The first and the last release
+    /// bucket are open-ended.
+    pub fn interval(&self, key: BucketKey) -> (Option<Timestamp>, Option<Timestamp>) {
+        match key {
+            BucketKey::Release(i) => {
+                let start = i.checked_sub(1).map(|i| self.releases[i].0);
+                let end = self.releases.get(i).map(|(t, _)| *t);
+                (start, end)
+            }
+            BucketKey::Date(_) => {
+                let timestamp = |key| match key {
+                    BucketKey::Date(date) => {
+                        let dt = DateTime::new(date.year(), date.month(), date.day(), 0, 0, 0, 0);
+                        self.tz.to_zoned(dt.unwrap()).unwrap().timestamp()
+                    }
+                    BucketKey::Release(_) => unreachable!(),
+                };
+                (Some(timestamp(key)), Some(timestamp(self.next(key))))
+            }
+        }
+    }
+}
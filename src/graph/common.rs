@@ -1,25 +1,68 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt,
+    hash::Hash,
+};
 
-use jiff::{civil::DateTime, tz::TimeZone, Timestamp, ToSpan, Unit};
+use anyhow::Context;
+use jiff::{tz::TimeZone, Timestamp};
+use unicode_width::UnicodeWidthStr;
 
 use crate::{
-    data::{Commit, Data},
-    progress,
+    data::{Commit, Data, NotFound, Oid, Tag},
+    progress, GlobalArgs, MissingMode,
 };
 
-pub fn first_hash(log: &[String], hash: Option<String>) -> anyhow::Result<String> {
-    if let Some(hash) = hash {
-        return Ok(hash);
+use super::{bucket::Bucketing, graph::Gap};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Days since the epoch between which the offsets of time zones are compared,
+/// roughly 1900 to 2100.
+const FIRST_DAY: i64 = -70 * 365;
+const LAST_DAY: i64 = 130 * 365;
+
+/// Most gaps listed individually, and most objects listed per gap.
+const MAX_REPORTED_GAPS: usize = 10;
+
+/// Resolve the time zone from the command line, the config file or the system,
+/// in that order.
+pub fn load_tz(data: &Data, global: &GlobalArgs) -> anyhow::Result<TimeZone> {
+    match global.tz.as_ref().or(data.config().tz.as_ref()) {
+        Some(name) if name.eq_ignore_ascii_case("utc") => Ok(TimeZone::UTC),
+        Some(name) => TimeZone::get(name).context(format!("unknown time zone {name}")),
+        None => Ok(TimeZone::system()),
+    }
+}
+
+/// The offsets a time zone uses, for telling apart counts computed in
+/// different time zones. Zones without a name, like fixed offsets or the
+/// system zone read from a file, are described by when their offset changes.
+/// Checking once a day is enough, since offsets never change more often.
+pub fn describe_tz(tz: &TimeZone) -> String {
+    let mut changes = vec![];
+    let mut prev = None;
+    for day in FIRST_DAY..LAST_DAY {
+        let time = Timestamp::from_second(day * SECONDS_PER_DAY).unwrap();
+        let offset = time.to_zoned(tz.clone()).offset();
+        if prev != Some(offset) {
+            changes.push(format!("{day}:{offset}"));
+            prev = Some(offset);
+        }
     }
+    changes.join(" ")
+}
 
-    if let Some(hash) = log.first() {
-        return Ok(hash.to_string());
+pub fn first_hash(log: &[Oid], hash: Option<Oid>) -> anyhow::Result<Oid> {
+    if let Some(hash) = hash.or(log.first().copied()) {
+        return Ok(hash);
     }
 
     anyhow::bail!("found no viable hash");
 }
 
-pub fn load_commits(data: &mut Data, log: Vec<String>) -> anyhow::Result<Vec<Commit>> {
+pub fn load_commits(data: &mut Data, log: Vec<Oid>) -> anyhow::Result<Vec<Commit>> {
     let pb = progress::counting_bar("Loading commits", log.len());
 
     let mut commits = vec![];
@@ -32,42 +75,292 @@ pub fn load_commits(data: &mut Data, log: Vec<String>) -> anyhow::Result<Vec<Com
     Ok(commits)
 }
 
-fn key(tz: &TimeZone, ts: Timestamp) -> (i16, i8) {
-    let dt = tz.to_datetime(ts);
-    (dt.year(), dt.month())
+/// Like [`load_commits`], but commits that can't be loaded are returned
+/// separately along with what is missing, to be reported as gaps.
+pub fn try_load_commits(data: &mut Data, log: Vec<Oid>) -> (Vec<Commit>, Vec<(Oid, MissingData)>) {
+    let pb = progress::counting_bar("Loading commits", log.len());
+
+    let mut commits = vec![];
+    let mut unloaded = vec![];
+    for hash in log {
+        match data.load_commit_cached(hash) {
+            Ok(commit) => commits.push(commit),
+            Err(e) => {
+                let mut missing = MissingData::default();
+                missing.add(None, e);
+                unloaded.push((hash, missing));
+            }
+        }
+        pb.inc(1);
+    }
+
+    pb.finish();
+    (commits, unloaded)
+}
+
+pub fn retain_tagged(
+    tags: &[Tag],
+    commits: &mut Vec<Commit>,
+    unloaded: &mut Vec<(Oid, MissingData)>,
+) -> anyhow::Result<()> {
+    let tagged = tags.iter().map(|t| &t.commit).collect::<HashSet<_>>();
+    commits.retain(|c| tagged.contains(&c.hash));
+    unloaded.retain(|(hash, _)| tagged.contains(hash));
+    if commits.is_empty() && unloaded.is_empty() {
+        anyhow::bail!("found no tagged commits, try gathering again");
+    }
+    Ok(())
 }
 
-pub fn order_for_equidistance(tz: &TimeZone, commits: &mut [Commit]) {
+pub fn order_for_equidistance(spacing: &Bucketing, commits: &mut [Commit]) {
     commits.reverse();
-    commits.sort_by_cached_key(|c| key(tz, c.committer_time));
+    commits.sort_by_cached_key(|c| spacing.key(c.committer_time));
     commits.reverse();
 }
 
-pub fn make_equidistant(tz: &TimeZone, times: &mut Vec<i64>) {
-    let mut time_by_key = BTreeMap::<(i16, i8), u32>::new();
+pub fn make_equidistant(spacing: &Bucketing, times: &mut Vec<i64>) {
+    let mut times_by_key = BTreeMap::<_, Vec<i64>>::new();
     for time in times.iter() {
         let ts = Timestamp::from_second(*time).unwrap();
-        *time_by_key.entry(key(tz, ts)).or_default() += 1;
+        times_by_key.entry(spacing.key(ts)).or_default().push(*time);
     }
 
     times.clear();
-    for ((year, month), amount) in time_by_key {
-        let amount: i64 = amount.into();
+    for (key, key_times) in times_by_key {
+        let amount: i64 = key_times.len().try_into().unwrap();
 
-        let start = DateTime::new(year, month, 1, 0, 0, 0, 0).unwrap();
-        let start = tz.to_zoned(start).unwrap();
-        let end = start.checked_add(1.month()).unwrap();
+        // Open-ended buckets only extend as far as their commits.
+        let (start, end) = spacing.interval(key);
+        let start = start.map_or(*key_times.iter().min().unwrap(), |t| t.as_second());
+        let end = end.map_or(*key_times.iter().max().unwrap() + 1, |t| t.as_second());
 
-        let seconds_interval = start.until((Unit::Second, &end)).unwrap().get_seconds();
-        let seconds_per_commit = seconds_interval / amount;
+        let seconds_per_commit = (end - start) / amount;
         for n in 0..amount {
             let seconds_since_start = seconds_per_commit * n + seconds_per_commit / 2;
-            let time = start
-                .checked_add(seconds_since_start.seconds())
-                .unwrap()
-                .timestamp()
-                .as_second();
-            times.push(time)
+            times.push(start + seconds_since_start)
         }
     }
 }
+
+/// Replace missing counts by linearly interpolating between the nearest known
+/// counts before and after them. At least one count must be known.
+fn interpolate<K: Clone + Eq + Hash>(counts: &mut [Option<HashMap<K, u64>>]) {
+    let known = (0..counts.len())
+        .filter(|i| counts[*i].is_some())
+        .collect::<Vec<_>>();
+
+    for i in 0..counts.len() {
+        if counts[i].is_some() {
+            continue;
+        }
+
+        let next = known.partition_point(|k| *k < i);
+        let prev = next.checked_sub(1).map(|j| known[j]);
+        let next = known.get(next).copied();
+        let count = match (prev, next) {
+            (Some(prev), Some(next)) => {
+                let t = (i - prev) as f64 / (next - prev) as f64;
+                let prev = counts[prev].as_ref().unwrap();
+                let next = counts[next].as_ref().unwrap();
+                prev.keys()
+                    .chain(next.keys())
+                    .map(|key| {
+                        let a = prev.get(key).copied().unwrap_or(0) as f64;
+                        let b = next.get(key).copied().unwrap_or(0) as f64;
+                        (key.clone(), (a + (b - a) * t).round() as u64)
+                    })
+                    .collect()
+            }
+            (Some(k), None) | (None, Some(k)) => counts[k].clone().unwrap(),
+            (None, None) => unreachable!("no known counts"),
+        };
+        counts[i] = Some(count);
+    }
+}
+
+/// The objects a commit couldn't be counted without.
+#[derive(Debug, Default)]
+pub struct MissingData {
+    /// Objects that don't exist.
+    pub not_found: Vec<String>,
+    /// Objects that exist but couldn't be loaded, along with the reason.
+    pub corrupt: Vec<String>,
+}
+
+impl MissingData {
+    /// Record an error loading an object, optionally needed for a file. If
+    /// the error isn't about an object that doesn't exist, it is expected to
+    /// name the object already.
+    pub fn add(&mut self, file: Option<&str>, e: anyhow::Error) {
+        match (NotFound::find_in(&e), file) {
+            (Some(nf), Some(file)) => {
+                (self.not_found).push(format!("{} {} for {file}", nf.kind, nf.key))
+            }
+            (Some(nf), None) => self.not_found.push(format!("{} {}", nf.kind, nf.key)),
+            (None, _) => self.corrupt.push(format!("{e:#}")),
+        }
+    }
+}
+
+impl fmt::Display for MissingData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.not_found[..], &self.corrupt[..]) {
+            ([name], []) => write!(f, "{name} not found"),
+            ([], [reason]) => write!(f, "{reason}"),
+            (not_found, corrupt) => {
+                let parts = [
+                    (not_found.len(), "not found"),
+                    (corrupt.len(), "can't be loaded"),
+                ];
+                let parts = parts
+                    .into_iter()
+                    .filter(|(n, _)| *n > 0)
+                    .map(|(n, what)| match n {
+                        1 => format!("1 object {what}"),
+                        n => format!("{n} objects {what}"),
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", parts.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for MissingData {}
+
+/// A commit along with the number of lines per key in it.
+pub type CommitCount<K> = (Commit, HashMap<K, u64>);
+
+/// Deal with commits whose counts couldn't be computed according to the
+/// policy, and report them. Commits that couldn't be loaded at all are always
+/// left out, since their time is unknown. Returns the commits to graph along
+/// with their counts, and the gaps.
+pub fn fill_gaps<K: Clone + Eq + Hash>(
+    mode: MissingMode,
+    counts: Vec<(Commit, anyhow::Result<HashMap<K, u64>>)>,
+    unloaded: Vec<(Oid, MissingData)>,
+) -> anyhow::Result<(Vec<CommitCount<K>>, Vec<Gap>)> {
+    let mut commits = vec![];
+    let mut known = vec![];
+    let mut gaps = unloaded
+        .into_iter()
+        .map(|(hash, missing)| Gap {
+            commit: hash,
+            reason: missing.to_string(),
+            not_found: missing.not_found,
+            corrupt: missing.corrupt,
+            interpolated: false,
+        })
+        .collect::<Vec<_>>();
+    let left_out = gaps.len();
+    for (commit, count) in counts {
+        match count {
+            Ok(count) => known.push(Some(count)),
+            Err(e) => {
+                let (reason, missing) = match e.downcast::<MissingData>() {
+                    Ok(missing) => (missing.to_string(), missing),
+                    Err(e) => (format!("{e:#}"), MissingData::default()),
+                };
+                gaps.push(Gap {
+                    commit: commit.hash,
+                    reason,
+                    not_found: missing.not_found,
+                    corrupt: missing.corrupt,
+                    interpolated: mode == MissingMode::Interpolate,
+                });
+                known.push(None);
+            }
+        }
+        commits.push(commit);
+    }
+
+    if !gaps.is_empty() {
+        let (n, total) = (gaps.len(), commits.len() + left_out);
+        match mode {
+            MissingMode::Skip => println!("Leaving out {n} of {total} commits with missing data:"),
+            MissingMode::Interpolate if left_out > 0 => println!(
+                "Interpolating {} and leaving out {left_out} of {total} commits with missing data:",
+                n - left_out
+            ),
+            MissingMode::Interpolate => {
+                println!("Interpolating {n} of {total} commits with missing data:")
+            }
+            MissingMode::Fail => println!("Missing data for {n} of {total} commits:"),
+        }
+        for gap in gaps.iter().take(MAX_REPORTED_GAPS) {
+            println!("  {}: {}", gap.commit, gap.reason);
+            let objects = gap.not_found.len() + gap.corrupt.len();
+            if objects > 1 {
+                let names = gap.not_found.iter().map(|name| format!("{name} not found"));
+                for object in names
+                    .chain(gap.corrupt.iter().cloned())
+                    .take(MAX_REPORTED_GAPS)
+                {
+                    println!("    {object}");
+                }
+                if objects > MAX_REPORTED_GAPS {
+                    println!("    and {} more", objects - MAX_REPORTED_GAPS);
+                }
+            }
+        }
+        if n > MAX_REPORTED_GAPS {
+            println!("  and {} more", n - MAX_REPORTED_GAPS);
+        }
+    }
+
+    if known.iter().all(|c| c.is_none()) {
+        anyhow::bail!("no commit has complete data, try gathering again");
+    }
+    match mode {
+        MissingMode::Fail if !gaps.is_empty() => {
+            anyhow::bail!("missing data, run fsck for details or see --missing")
+        }
+        MissingMode::Interpolate => interpolate(&mut known),
+        _ => {}
+    }
+
+    let counts = commits
+        .into_iter()
+        .zip(known)
+        .filter_map(|(commit, count)| Some((commit, count?)))
+        .collect();
+    Ok((counts, gaps))
+}
+
+/// Print one row per label with a shaded cell per hour of the day, followed by
+/// the row's total. Shades are relative to the largest value overall, or to
+/// the largest value in the row if `per_row` is set.
+pub fn print_hour_heatmap(rows: &[(String, [u64; 24])], per_row: bool) {
+    const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+    let label_width = rows.iter().map(|(l, _)| l.width()).max().unwrap_or(0);
+    let overall_max = rows.iter().flat_map(|(_, h)| h).copied().max().unwrap_or(0);
+
+    let header = (0..24)
+        .step_by(3)
+        .map(|h| format!("{h:<6}"))
+        .collect::<String>();
+    println!("{} {}", " ".repeat(label_width), header.trim_end());
+
+    for (label, hours) in rows {
+        let max = if per_row {
+            hours.iter().copied().max().unwrap_or(0)
+        } else {
+            overall_max
+        };
+        let cells = hours
+            .iter()
+            .map(|n| {
+                // Round up so that any activity at all is visible.
+                let shade = match max {
+                    0 => 0,
+                    max => (n * 4).div_ceil(max) as usize,
+                };
+                SHADES[shade].to_string().repeat(2)
+            })
+            .collect::<String>();
+        let total = hours.iter().sum::<u64>();
+        let label = format!("{label:label_width$}");
+        println!("{label} {cells} {total}");
+    }
+}
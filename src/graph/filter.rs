@@ -0,0 +1,111 @@
+use std::{fmt, path::PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::{
+    data::{Data, FileClass},
+    PathArgs,
+};
+
+fn build_gitignore(globs: &[String]) -> anyhow::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(PathBuf::new());
+    for glob in globs {
+        builder.add_line(None, glob)?;
+    }
+    Ok(builder.build()?)
+}
+
+/// The reason a file is not taken into account.
+pub enum Exclusion {
+    Ignored,
+    NotIncluded,
+    Excluded,
+    Class(FileClass),
+}
+
+impl fmt::Display for Exclusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ignored => write!(f, "ignored"),
+            Self::NotIncluded => write!(f, "not included"),
+            Self::Excluded => write!(f, "excluded"),
+            Self::Class(class) => write!(f, "{class}"),
+        }
+    }
+}
+
+/// Decides which files are taken into account when counting lines.
+///
+/// Combines the `ignore` file from the data directory with the `--include` and
+/// `--exclude` globs given on the command line. All of them use gitignore
+/// syntax. Optionally also skips files classified as vendored, generated or
+/// binary during gathering.
+pub struct PathFilter {
+    ignore: Gitignore,
+    include: Option<Gitignore>,
+    exclude: Gitignore,
+    linguist: bool,
+    /// The options the filter was built from, without the `ignore` file.
+    description: String,
+}
+
+impl PathFilter {
+    pub fn load(data: &Data, args: &PathArgs) -> anyhow::Result<Self> {
+        let ignore = data.load_ignore_uncached()?;
+        let include = if args.include.is_empty() {
+            None
+        } else {
+            Some(build_gitignore(&args.include)?)
+        };
+        let exclude = build_gitignore(&args.exclude)?;
+        Ok(Self {
+            ignore,
+            include,
+            exclude,
+            linguist: args.linguist,
+            description: format!("{:?} {:?} {}", args.include, args.exclude, args.linguist),
+        })
+    }
+
+    /// Everything the filter depends on except for the `ignore` file, for
+    /// telling apart counts computed with different filters.
+    pub fn describe(&self) -> &str {
+        &self.description
+    }
+
+    pub fn exclusion(&self, path: &str, class: Option<FileClass>) -> Option<Exclusion> {
+        if self
+            .ignore
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+        {
+            return Some(Exclusion::Ignored);
+        }
+
+        if let Some(include) = &self.include {
+            if !include.matched_path_or_any_parents(path, false).is_ignore() {
+                return Some(Exclusion::NotIncluded);
+            }
+        }
+
+        if self
+            .exclude
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+        {
+            return Some(Exclusion::Excluded);
+        }
+
+        if self.linguist {
+            if let Some(class) = class {
+                return Some(Exclusion::Class(class));
+            }
+        }
+
+        None
+    }
+
+    pub fn is_excluded(&self, path: &str, class: Option<FileClass>) -> bool {
+        self.exclusion(path, class).is_some()
+    }
+}
@@ -1,23 +1,54 @@
-use std::{fs, path::Path};
+use std::{collections::HashSet, fs, path::Path};
 
-use jiff::tz::TimeZone;
+use jiff::{civil::DateTime, tz::TimeZone};
 use serde::Serialize;
 
-use crate::{data::Commit, graph::common};
+use crate::{
+    data::{Annotation, Commit, Oid, Tag},
+    graph::common,
+};
 
-use super::series::Series;
+use super::{bucket::Bucketing, series::Series};
+
+/// A labelled position on the time axis.
+#[derive(Serialize)]
+struct Marker {
+    time: i64,
+    label: String,
+    description: String,
+}
+
+/// A commit whose values couldn't be computed because data is missing.
+#[derive(Serialize)]
+pub struct Gap {
+    pub commit: Oid,
+    pub reason: String,
+    /// Objects that don't exist.
+    pub not_found: Vec<String>,
+    /// Objects that exist but couldn't be loaded, along with the reason.
+    pub corrupt: Vec<String>,
+    /// Whether the commit is in the graph with interpolated values, as
+    /// opposed to being left out.
+    pub interpolated: bool,
+}
 
 #[derive(Serialize)]
 pub struct Graph {
     title: String,
+    /// IANA name of the time zone used for dates, if known.
+    tz: Option<String>,
     commits: Vec<Commit>,
     time: Vec<i64>,
     series: Vec<Series>,
+    tags: Vec<Tag>,
+    annotations: Vec<Marker>,
+    gaps: Vec<Gap>,
 }
 
 impl Graph {
     pub fn new(
         title: &str,
+        tz: &TimeZone,
         mut commits: Vec<Commit>,
         mut time: Vec<i64>,
         mut series: Vec<Series>,
@@ -30,14 +61,59 @@ impl Graph {
 
         Self {
             title: title.to_string(),
+            tz: tz.iana_name().map(|n| n.to_string()),
             commits,
             time,
             series,
+            tags: vec![],
+            annotations: vec![],
+            gaps: vec![],
         }
     }
 
-    pub fn make_equidistant(&mut self, tz: TimeZone) {
-        common::make_equidistant(&tz, &mut self.time);
+    /// Add tags pointing to commits in the graph, to be shown as markers.
+    pub fn add_tags(&mut self, tags: Vec<Tag>) {
+        let hashes = self.commits.iter().map(|c| &c.hash).collect::<HashSet<_>>();
+        let tags = tags.into_iter().filter(|t| hashes.contains(&t.commit));
+        self.tags.extend(tags);
+    }
+
+    pub fn add_gaps(&mut self, gaps: Vec<Gap>) {
+        self.gaps.extend(gaps);
+    }
+
+    pub fn make_equidistant(&mut self, spacing: &Bucketing) {
+        common::make_equidistant(spacing, &mut self.time);
+    }
+
+    /// Place annotations on the time axis. Annotations at commits use the
+    /// commit's position, annotations at dates use the position of the first
+    /// commit on or after that date. Annotations that can't be placed are
+    /// dropped.
+    ///
+    /// Must be called after the time axis has been made equidistant.
+    pub fn add_annotations(&mut self, annotations: Vec<Annotation>, tz: &TimeZone) {
+        for annotation in annotations {
+            let idx = if let Some(commit) = &annotation.commit {
+                self.commits
+                    .iter()
+                    .position(|c| c.hash.to_string().starts_with(commit))
+            } else if let Some(date) = annotation.date {
+                let dt = DateTime::new(date.year(), date.month(), date.day(), 0, 0, 0, 0).unwrap();
+                let ts = tz.to_zoned(dt).unwrap().timestamp();
+                self.commits.iter().position(|c| c.committer_time >= ts)
+            } else {
+                None
+            };
+
+            if let Some(idx) = idx {
+                self.annotations.push(Marker {
+                    time: self.time[idx],
+                    label: annotation.label,
+                    description: annotation.description,
+                });
+            }
+        }
     }
 
     pub fn save_json(&self, path: &Path) -> anyhow::Result<()> {
@@ -0,0 +1,201 @@
+use std::{collections::HashMap, hash::Hash};
+
+use jiff::{tz::TimeZone, Timestamp};
+
+use crate::{
+    data::{Authors, Commit, Data, Mailmap},
+    AuthorArgs, BotMode, CoAuthorMode, GlobalArgs, Role,
+};
+
+use super::common;
+
+/// Lines are credited in fractions of this many parts, so splitting lines
+/// between up to 16 people is exact. Only totals are rounded to whole lines,
+/// see [`round_credit`].
+const CREDIT_PARTS: u64 = 720_720;
+
+/// Round credited lines to whole lines.
+pub fn round_credit(credit: u64) -> u64 {
+    (credit + CREDIT_PARTS / 2) / CREDIT_PARTS
+}
+
+/// Round all credited lines of a count to whole lines.
+pub fn round_credits<K: Eq + Hash>(count: HashMap<K, u64>) -> HashMap<K, u64> {
+    count
+        .into_iter()
+        .map(|(key, credit)| (key, round_credit(credit)))
+        .collect()
+}
+
+/// Maps the author of a commit to the name shown in stats.
+///
+/// The mailmap is applied first, then `authors.toml`. When grouping by a
+/// dimension like teams, the author is then replaced by the group they
+/// belonged to at the time of the commit. Depending on the co-author mode,
+/// lines may also be credited to co-authors.
+///
+/// Whether the author or committer of a commit counts as its author, and
+/// whose date counts as its date, is configurable.
+pub struct Identities {
+    mailmap: Mailmap,
+    authors: Authors,
+    use_email: bool,
+    by: Option<String>,
+    bots: BotMode,
+    co_authors: CoAuthorMode,
+    identity: Role,
+    date: Role,
+    tz: TimeZone,
+}
+
+impl Identities {
+    pub fn load(
+        data: &Data,
+        global: &GlobalArgs,
+        args: &AuthorArgs,
+        bots: BotMode,
+    ) -> anyhow::Result<Self> {
+        let authors = data.load_authors_uncached()?;
+        if let Some(by) = &args.by {
+            if !authors.has_groups(by) {
+                anyhow::bail!("no groups.{by} table found in authors file");
+            }
+        }
+
+        Ok(Self {
+            mailmap: data.load_mailmap_uncached(args.mailmap.as_deref())?,
+            authors,
+            use_email: args.email,
+            by: args.by.clone(),
+            bots,
+            co_authors: args.co_authors,
+            identity: global.identity,
+            date: global.date,
+            tz: common::load_tz(data, global)?,
+        })
+    }
+
+    /// Everything the identities depend on except for the user files, for
+    /// telling apart counts computed with different options.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} {:?} {:?} {:?} {:?} {:?} {}",
+            self.use_email,
+            self.by,
+            self.bots,
+            self.co_authors,
+            self.identity,
+            self.date,
+            common::describe_tz(&self.tz),
+        )
+    }
+
+    /// What the names returned by [`Self::author`] refer to.
+    pub fn dimension(&self) -> &str {
+        self.by.as_deref().unwrap_or("author")
+    }
+
+    /// The date a commit's lines are attributed to.
+    pub fn date(&self, commit: &Commit) -> Timestamp {
+        match self.date {
+            Role::Author => commit.author_time,
+            Role::Committer => commit.committer_time,
+        }
+    }
+
+    fn person<'a>(&self, commit: &'a Commit) -> (&'a str, &'a str) {
+        match self.identity {
+            Role::Author => (&commit.author, &commit.author_mail),
+            Role::Committer => (&commit.committer, &commit.committer_mail),
+        }
+    }
+
+    fn is_bot(&self, name: &str, email: &str) -> bool {
+        let (name, email) = self.mailmap.get(name, email);
+        self.authors.is_bot(&name)
+            || self.authors.is_bot(&email)
+            || self.authors.is_bot(&self.authors.get(&name))
+    }
+
+    /// Whether lines from this commit should not be counted at all.
+    pub fn is_excluded(&self, commit: &Commit) -> bool {
+        let (name, email) = self.person(commit);
+        self.bots == BotMode::Exclude && self.is_bot(name, email)
+    }
+
+    fn resolve(&self, commit: &Commit, name: &str, email: &str) -> String {
+        let (name, email) = self.mailmap.get(name, email);
+        let author = if self.use_email { email } else { name };
+        let author = self.authors.get(&author);
+
+        let Some(by) = &self.by else {
+            return author;
+        };
+
+        let date = self.tz.to_datetime(self.date(commit)).date();
+        match self.authors.group(by, &author, date) {
+            Some(group) => group.to_string(),
+            None => format!("no {by}"),
+        }
+    }
+
+    pub fn author(&self, commit: &Commit) -> String {
+        let (name, email) = self.person(commit);
+        if self.is_bot(name, email) {
+            match self.bots {
+                BotMode::Fold => return "automation".to_string(),
+                // Commits made through a web interface are committed by a bot
+                // too, like GitHub's web-flow.
+                BotMode::Committer if self.is_bot(&commit.committer, &commit.committer_mail) => {
+                    return "automation".to_string();
+                }
+                BotMode::Committer => {
+                    return self.resolve(commit, &commit.committer, &commit.committer_mail)
+                }
+                BotMode::Keep | BotMode::Exclude => {}
+            }
+        }
+
+        self.resolve(commit, name, email)
+    }
+
+    fn co_author(&self, commit: &Commit, name: &str, email: &str) -> Option<String> {
+        if self.bots != BotMode::Keep && self.is_bot(name, email) {
+            return match self.bots {
+                BotMode::Fold => Some("automation".to_string()),
+                _ => None,
+            };
+        }
+        Some(self.resolve(commit, name, email))
+    }
+
+    /// Distribute the lines a commit contributed among its author and
+    /// co-authors, in parts of [`CREDIT_PARTS`]. When splitting, the remainder
+    /// goes to the author.
+    pub fn credit(&self, commit: &Commit, lines: u64) -> Vec<(String, u64)> {
+        let mut names = vec![self.author(commit)];
+        if self.co_authors != CoAuthorMode::Ignore {
+            for (name, email) in commit.co_authors() {
+                if let Some(name) = self.co_author(commit, name, email) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+
+        let credit = lines * CREDIT_PARTS;
+        if self.co_authors != CoAuthorMode::Split {
+            return names.into_iter().map(|n| (n, credit)).collect();
+        }
+
+        let n: u64 = names.len().try_into().unwrap();
+        let share = credit / n;
+        let remainder = credit % n;
+        names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (name, if i == 0 { share + remainder } else { share }))
+            .collect()
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Digest;
+
+use crate::data::{Data, Oid, Sha256};
+
+/// Length of the settings hash in aggregate keys. Collisions only matter
+/// between settings used with the same data directory, so this is plenty.
+const SETTINGS_LEN: usize = 16;
+
+/// Changed whenever the way lines are counted or which counts are stored
+/// changes, so counts computed by older versions of blamegraph are not used
+/// and gc removes them.
+const COUNTING_VERSION: u32 = 3;
+
+fn hash_settings(parts: &[&str]) -> String {
+    let mut hasher = sha2::Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    let hash = Sha256(hasher.finalize().into()).to_string();
+    hash[..SETTINGS_LEN].to_string()
+}
+
+/// The part of aggregate keys that depends on the user files and on how lines
+/// are counted, given the hash of the user files.
+fn files_part(files: &Sha256) -> String {
+    hash_settings(&[&COUNTING_VERSION.to_string(), &files.to_string()])
+}
+
+/// The files parts of aggregate keys that are still in use by the top level or
+/// any profile. Aggregates with other files parts are outdated.
+pub fn current_files_parts(data: &Data) -> anyhow::Result<HashSet<String>> {
+    let hashes = data.hash_user_files_of_all_profiles()?;
+    Ok(hashes.iter().map(files_part).collect())
+}
+
+/// Persisted line counts per commit, so stats can be computed again without
+/// loading any blames. Counts per blame are not stored, since there are far
+/// more blames than commits and each count is tiny.
+///
+/// Counts are stored under a hash of everything they depend on: the contents
+/// of the `ignore`, `authors.toml` and mailmap files and the options. When
+/// any of them change, the outdated counts are no longer found and fresh ones
+/// are computed. The hash of the files is kept separate so gc can tell which
+/// counts are outdated.
+pub struct Index {
+    files: String,
+    settings: String,
+    save: bool,
+}
+
+impl Index {
+    /// The `counting` settings affect the counts of each blame, the
+    /// `filtering` settings which blames are counted for a commit.
+    pub fn load(
+        data: &Data,
+        mailmap: Option<&Path>,
+        counting: &str,
+        filtering: &str,
+    ) -> anyhow::Result<Self> {
+        let files = files_part(&data.hash_user_files(mailmap)?);
+        let settings = hash_settings(&[counting, filtering]);
+        Ok(Self {
+            files,
+            settings,
+            save: true,
+        })
+    }
+
+    /// Only use existing counts and don't save new ones, so the data directory
+    /// is never written to.
+    pub fn without_saving(self) -> Self {
+        Self {
+            save: false,
+            ..self
+        }
+    }
+
+    fn commit_key(&self, hash: Oid) -> String {
+        format!("{hash}-{}-{}", self.files, self.settings)
+    }
+
+    pub fn load_commit<K>(&self, data: &Data, hash: Oid) -> anyhow::Result<Option<HashMap<K, u64>>>
+    where
+        K: Eq + Hash + DeserializeOwned,
+    {
+        data.load_aggregate(&self.commit_key(hash))
+    }
+
+    pub fn save_commit<K: Serialize>(
+        &self,
+        data: &Data,
+        hash: Oid,
+        count: &HashMap<K, u64>,
+    ) -> anyhow::Result<()> {
+        if !self.save {
+            return Ok(());
+        }
+        data.save_aggregate(&self.commit_key(hash), count)
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    sync::Arc,
+};
+
+use crate::data::{BlameId, FileClass, Oid};
+
+/// Lines per key of a single blame.
+type BlameCount<K> = Arc<HashMap<K, u64>>;
+
+/// Blames that couldn't be counted, along with the reason.
+pub type Failures = Vec<(BlameId, anyhow::Error)>;
+
+/// Line counts of a commit, updated to the counts of another commit by only
+/// looking at the blames that differ between the two.
+///
+/// Consecutive commits share most of their blames, so this is much cheaper
+/// than adding up the counts of every blame for every commit.
+pub struct RunningTotal<K> {
+    /// The commit whose blames are currently included, if any.
+    commit: Option<Oid>,
+    /// File classes of that commit, needed to filter the blames of a delta.
+    classes: HashMap<Arc<str>, FileClass>,
+    /// Ids and counts of the blames currently included in the total, by path.
+    blames: HashMap<Arc<str>, (BlameId, BlameCount<K>)>,
+    /// Lines per key, and how many blames contribute to the key. Keys are kept
+    /// while any blame has them, even with zero lines, like when adding up all
+    /// blames from scratch.
+    totals: HashMap<K, (u64, usize)>,
+}
+
+impl<K> Default for RunningTotal<K> {
+    fn default() -> Self {
+        Self {
+            commit: None,
+            classes: HashMap::new(),
+            blames: HashMap::new(),
+            totals: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> RunningTotal<K> {
+    /// The commit whose blames are currently included, if any.
+    pub fn commit(&self) -> Option<Oid> {
+        self.commit
+    }
+
+    /// File classes of [`Self::commit`].
+    pub fn classes(&self) -> &HashMap<Arc<str>, FileClass> {
+        &self.classes
+    }
+
+    /// Update the total to consist of exactly these blames of a commit. Only
+    /// the counts of blames not already included are requested.
+    ///
+    /// If counting any blame fails, the remaining blames are still tried so
+    /// all failures are returned, the total is reset and the next update
+    /// starts from scratch.
+    pub fn update<F>(
+        &mut self,
+        commit: Oid,
+        classes: HashMap<Arc<str>, FileClass>,
+        blames: HashSet<BlameId>,
+        count: F,
+    ) -> Result<(), Failures>
+    where
+        F: FnMut(&BlameId) -> anyhow::Result<BlameCount<K>>,
+    {
+        let removed = self
+            .blames
+            .values()
+            .filter(|(b, _)| !blames.contains(b))
+            .map(|(b, _)| b.path.clone())
+            .collect();
+        self.remove_paths(removed);
+
+        let added = blames
+            .into_iter()
+            .filter(|b| !self.blames.contains_key(&b.path))
+            .collect();
+        self.commit = Some(commit);
+        self.classes = classes;
+        self.add_blames(added, count)
+    }
+
+    /// Update the total from [`Self::commit`] to a commit stored as a delta to
+    /// it, given the paths the delta removes and the blames it changes. The
+    /// commit must have the same file classes.
+    ///
+    /// Failures are handled like in [`Self::update`].
+    pub fn apply_delta<F>(
+        &mut self,
+        commit: Oid,
+        removed: Vec<Arc<str>>,
+        changed: Vec<BlameId>,
+        count: F,
+    ) -> Result<(), Failures>
+    where
+        F: FnMut(&BlameId) -> anyhow::Result<BlameCount<K>>,
+    {
+        self.remove_paths(removed);
+        self.remove_paths(changed.iter().map(|b| b.path.clone()).collect());
+        self.commit = Some(commit);
+        self.add_blames(changed, count)
+    }
+
+    fn remove_paths(&mut self, paths: Vec<Arc<str>>) {
+        for path in paths {
+            if let Some((_, blame_count)) = self.blames.remove(&path) {
+                self.subtract(&blame_count);
+            }
+        }
+    }
+
+    fn add_blames<F>(&mut self, blames: Vec<BlameId>, mut count: F) -> Result<(), Failures>
+    where
+        F: FnMut(&BlameId) -> anyhow::Result<BlameCount<K>>,
+    {
+        let mut errors = vec![];
+        for blame_id in blames {
+            match count(&blame_id) {
+                Ok(blame_count) => {
+                    self.add(&blame_count);
+                    self.blames
+                        .insert(blame_id.path.clone(), (blame_id, blame_count));
+                }
+                Err(e) => errors.push((blame_id, e)),
+            }
+        }
+
+        if !errors.is_empty() {
+            *self = Self::default();
+            return Err(errors);
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, count: &HashMap<K, u64>) {
+        for (key, amount) in count {
+            let (lines, blames) = self.totals.entry(key.clone()).or_default();
+            *lines += amount;
+            *blames += 1;
+        }
+    }
+
+    fn subtract(&mut self, count: &HashMap<K, u64>) {
+        for (key, amount) in count {
+            let (lines, blames) = self.totals.get_mut(key).unwrap();
+            *lines -= amount;
+            *blames -= 1;
+            if *blames == 0 {
+                self.totals.remove(key);
+            }
+        }
+    }
+
+    pub fn counts(&self) -> HashMap<K, u64> {
+        self.totals
+            .iter()
+            .map(|(key, (lines, _))| (key.clone(), *lines))
+            .collect()
+    }
+}
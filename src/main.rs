@@ -1,12 +1,16 @@
 mod data;
+mod fsck;
 mod gather;
 mod graph;
+mod migrate;
 mod progress;
+mod stats;
+mod suggest;
 
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand, ValueEnum};
-use data::Data;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use data::{Backend, Data, Oid};
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum OutFormat {
@@ -15,30 +19,254 @@ enum OutFormat {
     Json,
 }
 
+/// How to treat lines written by bots.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum BotMode {
+    /// Treat bots like everyone else.
+    #[default]
+    Keep,
+    /// Don't count lines written by bots.
+    Exclude,
+    /// Combine all bots into a single "automation" author.
+    Fold,
+    /// Attribute lines written by bots to the committer of the commit, or
+    /// combine them like `fold` if the committer is a bot too.
+    Committer,
+}
+
+/// Whose identity or date a commit's lines are attributed to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Role {
+    /// Whoever originally wrote the commit.
+    #[default]
+    Author,
+    /// Whoever last applied the commit, for example when rebasing or merging
+    /// a pull request.
+    Committer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum BucketKind {
+    Year,
+    /// Year starting at the month given by --fiscal-start.
+    FiscalYear,
+    Quarter,
+    Month,
+    /// ISO week, starting on monday.
+    Week,
+    /// Interval between consecutive tags.
+    Release,
+}
+
+// Options that apply to all stats.
+#[derive(Debug, ClapArgs)]
+pub struct GlobalArgs {
+    /// Attribute lines to the author or the committer of a commit.
+    #[arg(long, value_enum, global = true, default_value_t = Default::default())]
+    identity: Role,
+    /// Date lines by the author or the committer date of a commit.
+    #[arg(long, value_enum, global = true, default_value_t = Default::default())]
+    date: Role,
+    /// Time zone for dates, either an IANA name or UTC. Defaults to the `tz`
+    /// entry in the config file, or the system time zone.
+    #[arg(long, global = true)]
+    tz: Option<String>,
+    /// Month fiscal years start in.
+    #[arg(long, global = true, default_value_t = 1, value_parser = clap::value_parser!(i8).range(1..=12))]
+    fiscal_start: i8,
+}
+
+/// What to do with commits whose blames are missing or can't be loaded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum MissingMode {
+    /// Leave the commits out of the graph.
+    #[default]
+    Skip,
+    /// Estimate the commits' values from the neighbouring commits.
+    Interpolate,
+    /// Fail instead of drawing a graph.
+    Fail,
+}
+
+// Options shared by all graphs.
+#[derive(Debug, ClapArgs)]
+pub struct GraphArgs {
+    outfile: Option<PathBuf>,
+    #[arg(value_enum, default_value_t=Default::default())]
+    format: OutFormat,
+    /// Space commits evenly within each of these buckets.
+    #[arg(long, value_enum, default_value_t = BucketKind::Month)]
+    spacing: BucketKind,
+    /// Only plot tagged commits, one point per commit even if it has several
+    /// tags.
+    #[arg(long, default_value_t = false)]
+    releases_only: bool,
+    /// What to do with commits whose data is missing. The fsck command lists
+    /// all missing data.
+    #[arg(long, value_enum, default_value_t = Default::default())]
+    missing: MissingMode,
+}
+
+/// How to credit co-authors from `Co-authored-by` trailers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CoAuthorMode {
+    /// Only credit the author.
+    #[default]
+    Ignore,
+    /// Split each line evenly between the author and co-authors.
+    Split,
+    /// Credit each line fully to the author and every co-author.
+    Full,
+}
+
+// Restrict stats to a subset of files, in addition to the `ignore` file.
+//
+// This and the other option structs have plain comments instead of doc
+// comments, since clap would show a doc comment as the description of every
+// command flattening the options.
+#[derive(Debug, ClapArgs)]
+pub struct PathArgs {
+    /// Only count files matching at least one of these gitignore-style globs.
+    #[arg(long)]
+    include: Vec<String>,
+    /// Don't count files matching any of these gitignore-style globs.
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Don't count files classified as vendored, generated or binary.
+    #[arg(long, short, default_value_t = false)]
+    linguist: bool,
+}
+
+// Restrict stats to a subset of files and decide which lines in them count.
+#[derive(Debug, ClapArgs)]
+pub struct FilterArgs {
+    #[command(flatten)]
+    paths: PathArgs,
+    /// How to treat lines written by bots, recognized by well-known names and
+    /// the `bots` entries in `authors.toml`.
+    #[arg(long, value_enum, default_value_t = Default::default())]
+    bots: BotMode,
+}
+
+// Control how commits are attributed to authors.
+#[derive(Debug, Default, ClapArgs)]
+pub struct AuthorArgs {
+    /// Identify authors by email instead of name.
+    #[arg(long, short, default_value_t = false)]
+    email: bool,
+    /// Use this mailmap instead of the one in the data directory.
+    #[arg(long)]
+    mailmap: Option<PathBuf>,
+    /// Attribute lines to groups from this `groups` table in the authors file
+    /// instead of individual authors, for example `team`.
+    #[arg(long)]
+    by: Option<String>,
+    /// How to credit co-authors from `Co-authored-by` trailers.
+    #[arg(long, value_enum, default_value_t = Default::default())]
+    co_authors: CoAuthorMode,
+}
+
 #[derive(Debug, Subcommand)]
 enum Command {
     Gather {
         repo: PathBuf,
+        /// Copy the repository's .mailmap into the data directory.
+        #[arg(long, default_value_t = false)]
+        mailmap: bool,
     },
     Authors {
-        hash: Option<String>,
-        #[arg(long, short, default_value_t = false)]
-        email: bool,
+        hash: Option<Oid>,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[command(flatten)]
+        author: AuthorArgs,
     },
     Years {
-        hash: Option<String>,
+        hash: Option<Oid>,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// How to group lines by the time they were written.
+        #[arg(long, value_enum, default_value_t = BucketKind::Year)]
+        age: BucketKind,
     },
-    GraphAuthors {
-        outfile: Option<PathBuf>,
-        #[arg(value_enum, default_value_t=Default::default())]
-        format: OutFormat,
+    /// List files that are not taken into account, and why.
+    Excluded {
+        hash: Option<Oid>,
+        #[command(flatten)]
+        paths: PathArgs,
+    },
+    /// Show when commits are made, by weekday and hour in the author's time
+    /// zone.
+    Activity {
+        /// How to treat commits made by bots.
+        #[arg(long, value_enum, default_value_t = Default::default())]
+        bots: BotMode,
+    },
+    /// Show at which hours of the day, in their own time zone, each author
+    /// wrote their surviving lines.
+    WorkingHours {
+        hash: Option<Oid>,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[command(flatten)]
+        author: AuthorArgs,
+    },
+    /// Suggest authors.toml entries for people with multiple identities.
+    SuggestAuthors {
+        /// Suggest mappings between emails instead of names.
         #[arg(long, short, default_value_t = false)]
         email: bool,
+        /// Append the suggestions to the authors file.
+        #[arg(long, short, default_value_t = false)]
+        write: bool,
+    },
+    /// Upgrade the generated data to the current format, and optionally move
+    /// it to another storage backend.
+    Migrate {
+        #[arg(long, value_enum)]
+        to: Option<Backend>,
+        /// The repository the data was gathered from, needed to upgrade data
+        /// gathered before format versions were recorded.
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Precompute line counts for all commits in the log, so graphs with the
+    /// same options don't need to load any blames. Graphs also save the counts
+    /// they compute.
+    Index {
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[command(flatten)]
+        author: AuthorArgs,
+        /// How to group lines by the time they were written.
+        #[arg(long, value_enum, default_value_t = BucketKind::Year)]
+        age: BucketKind,
+    },
+    /// Show how much space the generated data takes up, by kind of object.
+    Stats,
+    /// Check that all objects needed for the commits in the log exist and can
+    /// be loaded.
+    Fsck,
+    /// Remove objects not needed for the commits in the log, including blames
+    /// of files matched by the ignore files of the top level and all profiles,
+    /// and counts computed with outdated user files or an explicit mailmap.
+    Gc,
+    GraphAuthors {
+        #[command(flatten)]
+        graph: GraphArgs,
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[command(flatten)]
+        author: AuthorArgs,
     },
     GraphYears {
-        outfile: Option<PathBuf>,
-        #[arg(value_enum, default_value_t=Default::default())]
-        format: OutFormat,
+        #[command(flatten)]
+        graph: GraphArgs,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// How to group lines by the time they were written.
+        #[arg(long, value_enum, default_value_t = BucketKind::Year)]
+        age: BucketKind,
     },
 }
 
@@ -46,30 +274,93 @@ enum Command {
 struct Args {
     datadir: PathBuf,
 
+    /// Use the ignore rules and author mappings from `profiles/<PROFILE>/`.
+    #[arg(long, short, global = true)]
+    profile: Option<String>,
+
+    /// Memory to use for caches, for example `512MiB` or `2GB`. Defaults to
+    /// the `memory` entry in the config file, or 1GiB. Memory needed besides
+    /// the caches, like the line counts each thread keeps while counting
+    /// commits, comes on top of this.
+    #[arg(long, global = true, value_parser = data::parse_memory)]
+    memory: Option<u64>,
+
+    /// Show cache statistics when done.
+    #[arg(long, short, global = true, default_value_t = false)]
+    verbose: bool,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+
     #[command(subcommand)]
     cmd: Command,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    let mut data = Data::new(args.datadir);
+    // Only gathering fills a new data directory.
+    let create = matches!(args.cmd, Command::Gather { .. });
+    let mut data = Data::new(args.datadir, args.memory, create)?;
+    if let Some(profile) = args.profile {
+        data.select_profile(profile)?;
+    }
+    if !matches!(args.cmd, Command::Migrate { .. }) {
+        data.check_format_version()?;
+    }
 
     match args.cmd {
-        Command::Gather { repo } => gather::gather(&mut data, &repo)?,
-        Command::Authors { hash, email } => graph::print_authors(&mut data, hash, email)?,
-        Command::Years { hash } => graph::print_years(&mut data, hash)?,
+        Command::Gather { repo, mailmap } => gather::gather(&mut data, &repo, mailmap)?,
+        Command::Authors {
+            hash,
+            filter,
+            author,
+        } => graph::print_authors(&mut data, &args.global, hash, &filter, &author)?,
+        Command::Years { hash, filter, age } => {
+            graph::print_years(&mut data, &args.global, hash, &filter, age)?
+        }
+        Command::Excluded { hash, paths } => graph::print_excluded(&mut data, hash, &paths)?,
+        Command::Activity { bots } => graph::print_activity(&mut data, &args.global, bots)?,
+        Command::WorkingHours {
+            hash,
+            filter,
+            author,
+        } => graph::print_working_hours(&mut data, &args.global, hash, &filter, &author)?,
+        Command::SuggestAuthors { email, write } => {
+            suggest::suggest_authors(&mut data, email, write)?
+        }
+        Command::Migrate { to, repo } => migrate::migrate(&mut data, repo.as_deref(), to)?,
+        Command::Index {
+            filter,
+            author,
+            age,
+        } => graph::index(&mut data, &args.global, &filter, &author, age)?,
+        Command::Stats => stats::print_stats(&data)?,
+        Command::Fsck => fsck::fsck(&mut data)?,
+        Command::Gc => fsck::gc(&mut data)?,
         Command::GraphAuthors {
-            outfile,
-            format,
-            email,
+            graph,
+            filter,
+            author,
         } => {
-            let outfile = outfile.unwrap_or_else(|| data.dir.join("authors.html"));
-            graph::graph_authors(&mut data, &outfile, format, email)?
+            let outfile = graph
+                .outfile
+                .clone()
+                .unwrap_or_else(|| data.default_outfile("authors", "html"));
+            let global = &args.global;
+            graph::graph_authors(&mut data, global, &outfile, &graph, &filter, &author)?
         }
-        Command::GraphYears { outfile, format } => {
-            let outfile = outfile.unwrap_or_else(|| data.dir.join("years.html"));
-            graph::graph_years(&mut data, &outfile, format)?
+        Command::GraphYears { graph, filter, age } => {
+            let outfile = graph
+                .outfile
+                .clone()
+                .unwrap_or_else(|| data.default_outfile("years", "html"));
+            let global = &args.global;
+            graph::graph_years(&mut data, global, &outfile, &graph, &filter, age)?
         }
     }
+
+    if args.verbose {
+        stats::print_cache_stats(&data);
+    }
     Ok(())
 }
@@ -0,0 +1,558 @@
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path, sync::Arc};
+
+use anyhow::Context;
+use bincode::Options;
+use jiff::Timestamp;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    data::{
+        self, Backend, Blame, BlameId, BlameTree, BlameTreeDelta, Commit, Data, DatabaseStore,
+        FileClass, FileStore, Kind, Oid, Store, StoredBlameTree, Tag, FORMAT_VERSION,
+    },
+    gather::Recovery,
+    progress,
+};
+
+/// Types as stored in format version 1, before trailers, UTC offsets and file
+/// classes were stored.
+mod v1 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct Commit {
+        pub hash: String,
+        pub parents: Vec<String>,
+        pub author: String,
+        pub author_mail: String,
+        pub author_time: Timestamp,
+        pub committer: String,
+        pub committer_mail: String,
+        pub committer_time: Timestamp,
+        pub subject: String,
+    }
+
+    impl Commit {
+        /// Add the trailers and the UTC offset from the commit as gathered
+        /// now, which has the same hash.
+        pub fn upgrade(self, gathered: &super::Commit) -> v3::Commit {
+            v3::Commit {
+                hash: self.hash,
+                parents: self.parents,
+                author: self.author,
+                author_mail: self.author_mail,
+                author_time: self.author_time,
+                author_offset: gathered.author_offset,
+                committer: self.committer,
+                committer_mail: self.committer_mail,
+                committer_time: self.committer_time,
+                subject: self.subject,
+                trailers: gathered.trailers.clone(),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct BlameTree {
+        pub commit: String,
+        pub blames: Vec<v3::BlameId>,
+    }
+
+    impl BlameTree {
+        pub fn upgrade(self, recovery: &mut Recovery) -> anyhow::Result<v3::BlameTree> {
+            let mut files = HashMap::new();
+            for blame in &self.blames {
+                files.insert(Arc::from(&*blame.path), blame.blob.parse()?);
+            }
+            let classes = recovery
+                .classify(&files)?
+                .into_iter()
+                .map(|(path, class)| (path.to_string(), class))
+                .collect();
+            Ok(v3::BlameTree {
+                commit: self.commit,
+                blames: self.blames,
+                classes,
+            })
+        }
+    }
+}
+
+/// Types as stored in format versions 2 and 3, with hashes as hex strings.
+mod v3 {
+    use super::*;
+
+    fn oids(hashes: Vec<String>) -> anyhow::Result<Vec<Oid>> {
+        hashes.iter().map(|h| h.parse()).collect()
+    }
+
+    fn classes(classes: HashMap<String, FileClass>) -> HashMap<Arc<str>, FileClass> {
+        classes.into_iter().map(|(p, c)| (p.into(), c)).collect()
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Commit {
+        pub hash: String,
+        pub parents: Vec<String>,
+        pub author: String,
+        pub author_mail: String,
+        pub author_time: Timestamp,
+        pub author_offset: i32,
+        pub committer: String,
+        pub committer_mail: String,
+        pub committer_time: Timestamp,
+        pub subject: String,
+        pub trailers: Vec<(String, String)>,
+    }
+
+    impl Commit {
+        pub fn upgrade(self) -> anyhow::Result<super::Commit> {
+            Ok(super::Commit {
+                hash: self.hash.parse()?,
+                parents: oids(self.parents)?,
+                author: self.author,
+                author_mail: self.author_mail,
+                author_time: self.author_time,
+                author_offset: self.author_offset,
+                committer: self.committer,
+                committer_mail: self.committer_mail,
+                committer_time: self.committer_time,
+                subject: self.subject,
+                trailers: self.trailers,
+            })
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BlameId {
+        pub commit: String,
+        pub blob: String,
+        pub path: String,
+    }
+
+    impl BlameId {
+        pub fn upgrade(self) -> anyhow::Result<super::BlameId> {
+            Ok(super::BlameId {
+                commit: self.commit.parse()?,
+                blob: self.blob.parse()?,
+                path: self.path.into(),
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct Blame {
+        pub id: BlameId,
+        pub lines_by_commit: HashMap<String, u64>,
+    }
+
+    impl Blame {
+        pub fn upgrade(self) -> anyhow::Result<super::Blame> {
+            let mut lines_by_commit = HashMap::new();
+            for (hash, lines) in self.lines_by_commit {
+                lines_by_commit.insert(hash.parse()?, lines);
+            }
+            Ok(super::Blame {
+                id: self.id.upgrade()?,
+                lines_by_commit,
+            })
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BlameTree {
+        pub commit: String,
+        pub blames: Vec<BlameId>,
+        pub classes: HashMap<String, FileClass>,
+    }
+
+    impl BlameTree {
+        pub fn upgrade(self) -> anyhow::Result<super::BlameTree> {
+            Ok(super::BlameTree {
+                commit: self.commit.parse()?,
+                blames: self
+                    .blames
+                    .into_iter()
+                    .map(BlameId::upgrade)
+                    .collect::<Result<_, _>>()?,
+                classes: classes(self.classes),
+            })
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BlameTreeDelta {
+        pub commit: String,
+        pub parent: String,
+        pub depth: u32,
+        pub removed: Vec<String>,
+        pub changed: Vec<BlameId>,
+        pub classes: Option<HashMap<String, FileClass>>,
+    }
+
+    impl BlameTreeDelta {
+        pub fn upgrade(self) -> anyhow::Result<super::BlameTreeDelta> {
+            Ok(super::BlameTreeDelta {
+                commit: self.commit.parse()?,
+                parent: self.parent.parse()?,
+                depth: self.depth,
+                removed: self.removed.into_iter().map(Arc::from).collect(),
+                changed: self
+                    .changed
+                    .into_iter()
+                    .map(BlameId::upgrade)
+                    .collect::<Result<_, _>>()?,
+                classes: self.classes.map(classes),
+            })
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum StoredBlameTree {
+        Full(BlameTree),
+        Delta(BlameTreeDelta),
+    }
+
+    #[derive(Deserialize)]
+    pub struct Tag {
+        pub name: String,
+        pub commit: String,
+    }
+
+    impl Tag {
+        pub fn upgrade(self) -> anyhow::Result<super::Tag> {
+            Ok(super::Tag {
+                name: self.name,
+                commit: self.commit.parse()?,
+            })
+        }
+    }
+}
+
+/// Deserialize the bytes as an object of the given type and nothing else, so
+/// objects of a similar type with more fields are not mistaken for it.
+fn deserialize_exact<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize::<T>(bytes)
+}
+
+/// Whether the bytes are an object of the given type and nothing else.
+fn is_serialized<T: DeserializeOwned>(bytes: &[u8]) -> bool {
+    deserialize_exact::<T>(bytes).is_ok()
+}
+
+/// Rewrite a compressed object from an old type to a new one, saving it under
+/// the key returned alongside it and removing the old key.
+///
+/// Objects that already are of the new type are skipped, so an interrupted
+/// upgrade can simply be run again.
+fn upgrade_compressed<Old, New>(
+    data: &Data,
+    kind: Kind,
+    key: &str,
+    upgrade: fn(Old) -> anyhow::Result<(String, New)>,
+) -> anyhow::Result<()>
+where
+    Old: DeserializeOwned,
+    New: Serialize + DeserializeOwned,
+{
+    let Some(value) = data.load_serialized(kind, key)? else {
+        return Ok(());
+    };
+    if is_serialized::<New>(&value) {
+        return Ok(());
+    }
+
+    let old =
+        bincode::deserialize::<Old>(&value).context(format!("failed to upgrade {kind} {key}"))?;
+    let (new_key, new) = upgrade(old)?;
+    data.save_serialized(kind, &new_key, &bincode::serialize(&new)?)?;
+    if new_key != key {
+        data.store().remove(kind, key)?;
+    }
+    Ok(())
+}
+
+fn upgrade_all_compressed<Old, New>(
+    data: &Data,
+    kind: Kind,
+    upgrade: fn(Old) -> anyhow::Result<(String, New)>,
+) -> anyhow::Result<()>
+where
+    Old: DeserializeOwned,
+    New: Serialize + DeserializeOwned,
+{
+    let keys = data.store().keys(kind)?;
+    let pb = progress::counting_bar(format!("Upgrading {kind}s"), keys.len());
+    for key in keys {
+        upgrade_compressed(data, kind, &key, upgrade)?;
+        pb.inc(1);
+    }
+    pb.finish();
+    Ok(())
+}
+
+/// Rewrite all uncompressed objects of a kind from an old type to a new one.
+/// Objects the upgrade returns `None` for are removed, and how many were
+/// removed is returned.
+///
+/// Objects that already are of the new type are skipped, so an interrupted
+/// upgrade can simply be run again.
+fn upgrade_uncompressed<Old, New>(
+    store: &dyn Store,
+    kind: Kind,
+    mut upgrade: impl FnMut(Old) -> anyhow::Result<Option<New>>,
+) -> anyhow::Result<usize>
+where
+    Old: DeserializeOwned,
+    New: Serialize + DeserializeOwned,
+{
+    let keys = store.keys(kind)?;
+    let pb = progress::counting_bar(format!("Upgrading {kind}s"), keys.len());
+    let mut removed = 0;
+    for key in keys {
+        let Some(value) = store.get(kind, &key)? else {
+            anyhow::bail!("{kind} {key} disappeared during upgrade");
+        };
+        if is_serialized::<New>(&value) {
+            pb.inc(1);
+            continue;
+        }
+
+        let old = deserialize_exact::<Old>(&value).context(format!(
+            "{kind} {key} is in an unknown format, gather into a new data directory instead"
+        ))?;
+        match upgrade(old).context(format!("failed to upgrade {kind} {key}"))? {
+            Some(new) => store.put(kind, &key, &bincode::serialize(&new)?)?,
+            None => {
+                store.remove(kind, &key)?;
+                removed += 1;
+            }
+        }
+        pb.inc(1);
+    }
+    pb.finish();
+    Ok(removed)
+}
+
+/// Commits gained trailers and the author's UTC offset, and blametrees gained
+/// file classes. All of them are recovered from the repository. Commits that
+/// are no longer reachable from its HEAD are removed.
+fn upgrade_from_v1(store: &dyn Store, recovery: &mut Recovery) -> anyhow::Result<()> {
+    let removed = upgrade_uncompressed(store, Kind::Commit, |commit: v1::Commit| {
+        let gathered = recovery.commit(commit.hash.parse()?);
+        Ok(gathered.map(|gathered| commit.upgrade(gathered)))
+    })?;
+    upgrade_uncompressed(store, Kind::BlameTree, |blametree: v1::BlameTree| {
+        Ok(Some(blametree.upgrade(recovery)?))
+    })?;
+    if removed > 0 {
+        println!(
+            "Removed {removed} commits that are no longer in the repository, run the gather \
+             command again to update the log"
+        );
+    }
+    Ok(())
+}
+
+/// Objects became compressed, and blametrees may be stored as deltas. Turning
+/// blametrees into deltas and training dictionaries is left to the upgrade to
+/// version 4, which rewrites all objects anyways.
+fn upgrade_from_v2(data: &Data) -> anyhow::Result<()> {
+    for kind in [Kind::Commit, Kind::BlameTree, Kind::Blame] {
+        let keys = data.store().keys(kind)?;
+        let pb = progress::counting_bar(format!("Compressing {kind}s"), keys.len());
+        for key in keys {
+            let Some(value) = data.store().get(kind, &key)? else {
+                anyhow::bail!("{kind} {key} disappeared during upgrade");
+            };
+            if data::is_compressed(&value) {
+                pb.inc(1);
+                continue;
+            }
+            let value = match kind {
+                Kind::BlameTree => {
+                    bincode::serialize(&v3::StoredBlameTree::Full(bincode::deserialize(&value)?))?
+                }
+                _ => value,
+            };
+            data.save_serialized(kind, &key, &value)?;
+            pb.inc(1);
+        }
+        pb.finish();
+    }
+    Ok(())
+}
+
+/// Hashes became binary, which changes the keys of blames. Blametrees stored
+/// in full are turned into deltas where possible. Since all objects change
+/// shape, the compression dictionaries are trained again.
+fn upgrade_from_v3(data: &mut Data) -> anyhow::Result<()> {
+    data.retire_dictionaries();
+
+    upgrade_compressed(data, Kind::Meta, "log", |log: Vec<String>| {
+        let log = log
+            .iter()
+            .map(|h| h.parse())
+            .collect::<Result<Vec<Oid>, _>>()?;
+        Ok(("log".to_string(), log))
+    })?;
+    upgrade_compressed(data, Kind::Meta, "tags", |tags: Vec<v3::Tag>| {
+        let tags = tags
+            .into_iter()
+            .map(v3::Tag::upgrade)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(("tags".to_string(), tags))
+    })?;
+    upgrade_all_compressed(data, Kind::Commit, |c: v3::Commit| {
+        Ok((c.hash.clone(), c.upgrade()?))
+    })?;
+    upgrade_all_compressed(data, Kind::Blame, |b: v3::Blame| {
+        let blame = b.upgrade()?;
+        Ok((blame.id.sha256().to_string(), blame))
+    })?;
+
+    // Parents come before their children in reverse log order, so the parent
+    // has already been upgraded when a delta to it is computed. Blametrees of
+    // commits not in the log are kept as they are.
+    let log = data.load_log_uncached()?;
+    let mut keys = data.store().keys(Kind::BlameTree)?;
+    keys.sort_unstable();
+    let pb = progress::counting_bar("Upgrading blametrees", keys.len());
+    let in_log = log.iter().rev().map(|hash| (hash.to_string(), true));
+    for (key, in_log) in in_log.chain(keys.into_iter().map(|key| (key, false))) {
+        let Some(value) = data.load_serialized(Kind::BlameTree, &key)? else {
+            continue;
+        };
+        if is_serialized::<StoredBlameTree>(&value) {
+            continue;
+        }
+        match bincode::deserialize::<v3::StoredBlameTree>(&value)? {
+            v3::StoredBlameTree::Full(blametree) => {
+                let blametree = blametree.upgrade()?;
+                // The commit is missing if it was removed when upgrading from
+                // version 1 and not gathered again yet.
+                let has_commit = data.store().contains(Kind::Commit, &key)?;
+                let parent = match in_log && has_commit {
+                    true => data
+                        .load_commit_cached(blametree.commit)?
+                        .parents
+                        .first()
+                        .copied(),
+                    false => None,
+                };
+                data.overwrite_blametree(&blametree, parent)?;
+            }
+            v3::StoredBlameTree::Delta(delta) => {
+                let delta = StoredBlameTree::Delta(delta.upgrade()?);
+                data.save_serialized(Kind::BlameTree, &key, &bincode::serialize(&delta)?)?;
+            }
+        }
+        pb.inc(1);
+    }
+    pb.finish();
+
+    // Every object has been rewritten without the old dictionaries by now.
+    data.remove_retired_dictionaries()?;
+    data.train_dictionaries()
+}
+
+/// Upgrade the stored objects to the current format version, one version at a
+/// time. Upgrading from version 1 needs the repository the data was gathered
+/// from.
+fn upgrade(data: &mut Data, repo: Option<&Path>) -> anyhow::Result<()> {
+    let Some(mut version) = data.load_format_version()? else {
+        return Ok(());
+    };
+    if version > FORMAT_VERSION {
+        return data.check_format_version();
+    }
+    let mut recovery = match (version, repo) {
+        (1, Some(repo)) => Some(Recovery::new(repo)?),
+        (1, None) => anyhow::bail!(
+            "data from format version 1 lacks information only the repository has, run \
+             migrate again with --repo"
+        ),
+        _ => None,
+    };
+
+    while version < FORMAT_VERSION {
+        println!("Upgrading from format version {version} to {}", version + 1);
+        match version {
+            1 => upgrade_from_v1(data.store(), recovery.as_mut().unwrap())?,
+            2 => upgrade_from_v2(data)?,
+            3 => upgrade_from_v3(data)?,
+            _ => unreachable!(),
+        }
+        version += 1;
+        data.save_format_version(version)?;
+        data.flush()?;
+    }
+    Ok(())
+}
+
+fn copy_objects(from: &dyn Store, to: &dyn Store) -> anyhow::Result<()> {
+    for kind in Kind::ALL {
+        let keys = from.keys(kind)?;
+        let pb = progress::counting_bar(format!("Copying {kind}s"), keys.len());
+        for key in keys {
+            let Some(value) = from.get(kind, &key)? else {
+                anyhow::bail!("{kind} {key} disappeared during migration");
+            };
+            to.put(kind, &key, &value)?;
+            pb.inc(1);
+        }
+        pb.finish();
+    }
+    to.flush()
+}
+
+/// Copy all generated objects into a new store of the given backend, then
+/// remove the old one.
+///
+/// The new store only takes effect once it is complete, so an interrupted
+/// migration leaves the data directory usable with the old backend.
+fn move_store(data: &mut Data, to: Backend) -> anyhow::Result<()> {
+    let from = data.store().backend();
+    if from == to {
+        println!("Data is already stored as {to}");
+        return Ok(());
+    }
+
+    let new: Box<dyn Store> = match to {
+        Backend::Files => {
+            // Files are only used once the database is gone.
+            let new = FileStore::new(data.dir.clone());
+            copy_objects(data.store(), &new)?;
+            Box::new(new)
+        }
+        Backend::Database => {
+            // Build the database under a temporary name so it is only picked
+            // up once it is complete.
+            let path = DatabaseStore::path(&data.dir);
+            let tmp_path = path.with_extension("redb.tmp");
+            match fs::remove_file(&tmp_path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                result => result?,
+            }
+            let tmp = DatabaseStore::open_at(tmp_path)?;
+            copy_objects(data.store(), &tmp)?;
+            tmp.rename(path)?;
+            Box::new(DatabaseStore::open(&data.dir)?)
+        }
+    };
+
+    println!("Removing {from} store");
+    data.replace_store(new)?.destroy()?;
+    Ok(())
+}
+
+pub fn migrate(data: &mut Data, repo: Option<&Path>, to: Option<Backend>) -> anyhow::Result<()> {
+    upgrade(data, repo)?;
+    if let Some(to) = to {
+        move_store(data, to)?;
+    }
+    Ok(())
+}
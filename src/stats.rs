@@ -0,0 +1,94 @@
+//! Show how much space the generated data takes up and how well caches work.
+
+use crate::data::{Data, Kind};
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Print rows with aligned columns. The first column is aligned to the left,
+/// all others to the right.
+fn print_table<const N: usize>(rows: &[[String; N]]) {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in rows {
+        let cells = (row.iter().zip(widths).enumerate())
+            .map(|(i, (cell, width))| match i {
+                0 => format!("{cell:width$}"),
+                _ => format!("{cell:>width$}"),
+            })
+            .collect::<Vec<_>>();
+        println!("{}", cells.join("  "));
+    }
+}
+
+pub fn print_stats(data: &Data) -> anyhow::Result<()> {
+    let store = data.store();
+    println!("Stored as {}", store.backend());
+    println!();
+
+    let mut rows = vec![["kind", "objects", "size", "average"].map(String::from)];
+    let (mut objects, mut bytes) = (0, 0);
+    for kind in Kind::ALL {
+        let usage = store.usage(kind)?;
+        let average = usage.bytes.checked_div(usage.objects).unwrap_or(0);
+        rows.push([
+            kind.to_string(),
+            usage.objects.to_string(),
+            format_bytes(usage.bytes),
+            format_bytes(average),
+        ]);
+        objects += usage.objects;
+        bytes += usage.bytes;
+    }
+    rows.push([
+        "total".to_string(),
+        objects.to_string(),
+        format_bytes(bytes),
+        String::new(),
+    ]);
+
+    print_table(&rows);
+
+    Ok(())
+}
+
+/// Show how well the caches worked for the command that just ran.
+pub fn print_cache_stats(data: &Data) {
+    let header = ["cache", "hits", "misses", "hit rate", "entries", "size"];
+    let mut rows = vec![header.map(String::from)];
+    for (name, stats) in data.cache_stats() {
+        let lookups = stats.hits + stats.misses;
+        let hit_rate = match lookups {
+            0 => String::new(),
+            _ => format!("{:.1}%", 100.0 * stats.hits as f64 / lookups as f64),
+        };
+        rows.push([
+            name,
+            stats.hits.to_string(),
+            stats.misses.to_string(),
+            hit_rate,
+            stats.entries.to_string(),
+            format_bytes(stats.bytes),
+        ]);
+    }
+
+    println!();
+    print_table(&rows);
+}
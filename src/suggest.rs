@@ -0,0 +1,140 @@
+//! Suggest `authors.toml` entries for people committing under multiple
+//! identities.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    data::{Authors, Data},
+    progress,
+};
+
+/// Lowercase alphanumeric words of a name, sorted, so that "Doe, Jane" and
+/// "jane doe" end up the same.
+fn normalize_name(name: &str) -> String {
+    let mut words = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>();
+    words.sort_unstable();
+    words.join(" ")
+}
+
+/// Union-find over arbitrary string keys.
+#[derive(Default)]
+struct Clusters {
+    index: HashMap<String, usize>,
+    parent: Vec<usize>,
+}
+
+impl Clusters {
+    fn node(&mut self, key: String) -> usize {
+        let next = self.parent.len();
+        let i = *self.index.entry(key).or_insert(next);
+        if i == next {
+            self.parent.push(i);
+        }
+        i
+    }
+
+    fn root(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn join(&mut self, a: String, b: String) {
+        let a = self.node(a);
+        let b = self.node(b);
+        let a = self.root(a);
+        let b = self.root(b);
+        self.parent[a] = b;
+    }
+}
+
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+pub fn suggest_authors(data: &mut Data, use_email: bool, write: bool) -> anyhow::Result<()> {
+    let log = data.load_log_uncached()?;
+    let mailmap = data.load_mailmap_uncached(None)?;
+    let mut authors = data.load_authors_uncached()?;
+
+    let pb = progress::counting_bar("Loading commits", log.len());
+    let mut commits_by_identity = HashMap::<(String, String), u64>::new();
+    for hash in log {
+        let commit = data.load_commit_cached(hash)?;
+        let (name, email) = mailmap.get(&commit.author, &commit.author_mail);
+        let name = authors.get(&name);
+        let email = authors.get(&email);
+        *commits_by_identity.entry((name, email)).or_default() += 1;
+        pb.inc(1);
+    }
+    pb.finish();
+
+    // Identities belong together if they share a name, an email, or a name
+    // that is the same after normalization.
+    let mut clusters = Clusters::default();
+    for (name, email) in commits_by_identity.keys() {
+        let name_key = format!("name {name}");
+        clusters.join(name_key.clone(), format!("email {}", email.to_lowercase()));
+        clusters.join(name_key, format!("normalized {}", normalize_name(name)));
+    }
+
+    let mut members = HashMap::<usize, HashMap<&str, u64>>::new();
+    for ((name, email), commits) in &commits_by_identity {
+        let member = if use_email { email } else { name };
+        let node = clusters.node(format!("name {name}"));
+        let root = clusters.root(node);
+        *members.entry(root).or_default().entry(member).or_default() += commits;
+    }
+
+    // The most common spelling in a cluster becomes the canonical one.
+    let mut suggestions = BTreeMap::<&str, Vec<&str>>::new();
+    for cluster in members.values() {
+        if cluster.len() < 2 {
+            continue;
+        }
+        let mut cluster = cluster.iter().collect::<Vec<_>>();
+        cluster.sort_unstable_by_key(|(member, commits)| (std::cmp::Reverse(**commits), **member));
+        let canonical = *cluster[0].0;
+        // Reserved names can't be renamed by top-level keys.
+        let mut variants = cluster[1..]
+            .iter()
+            .map(|(m, _)| **m)
+            .filter(|m| !Authors::is_reserved(m))
+            .collect::<Vec<_>>();
+        if variants.is_empty() {
+            continue;
+        }
+        variants.sort_unstable();
+        suggestions.insert(canonical, variants);
+    }
+
+    if suggestions.is_empty() {
+        println!("No suggestions");
+        return Ok(());
+    }
+
+    let mut toml = String::new();
+    for (canonical, variants) in &suggestions {
+        toml.push_str(&format!("\n# {canonical}\n"));
+        for variant in variants {
+            toml.push_str(&format!("{} = {}\n", quote(variant), quote(canonical)));
+            authors.insert(variant.to_string(), canonical.to_string());
+        }
+    }
+
+    if write {
+        authors.check_for_cycles()?;
+        data.add_authors(&toml)?;
+        println!("Added suggestions to authors file");
+    } else {
+        print!("{}", toml.trim_start());
+    }
+
+    Ok(())
+}